@@ -0,0 +1,224 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, path::PathBuf};
+
+use crate::{GameAction, GameState, Par};
+
+/// Where the profile is kept in the browser's local storage in the web build.
+#[cfg(target_arch = "wasm32")]
+const STORAGE_KEY: &str = "pestilence-campaign";
+
+/// How well a level went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelRecord {
+    /// Parasite turns taken, counting the winning one.
+    pub turns: usize,
+    /// DNA left over at the end.
+    pub dna: u16,
+    /// Infected units that were killed.
+    #[serde(default)]
+    pub parasites_lost: usize,
+    /// From 1 to 3, against the level's par.
+    #[serde(default)]
+    pub stars: u8,
+}
+
+impl LevelRecord {
+    pub fn new(game: &GameState) -> Self {
+        let mut infected: Vec<usize> = game
+            .actions
+            .iter()
+            .filter_map(|action| match action {
+                GameAction::Infect { id } => Some(*id),
+                _ => None,
+            })
+            .collect();
+        infected.sort();
+        infected.dedup();
+
+        let mut record = Self {
+            turns: game
+                .actions
+                .split_inclusive(|action| *action == GameAction::EndTurn)
+                .count(),
+            dna: game.dna,
+            parasites_lost: infected
+                .iter()
+                .filter(|id| game.unit(**id).is_none())
+                .count(),
+            stars: 0,
+        };
+        record.stars = record.rate(&game.level.par);
+        record
+    }
+
+    /// Which of the par values were met: turns, DNA and parasites lost.
+    pub fn met(&self, par: &Par) -> [bool; 3] {
+        [
+            self.turns <= par.turns,
+            self.dna >= par.dna,
+            self.parasites_lost <= par.parasites_lost,
+        ]
+    }
+
+    /// One star for winning, two for meeting any par value and three for meeting all of them.
+    pub fn rate(&self, par: &Par) -> u8 {
+        match self.met(par).iter().filter(|met| **met).count() {
+            3 => 3,
+            0 => 1,
+            _ => 2,
+        }
+    }
+
+    /// More stars is better, then fewer turns, more DNA left and fewer parasites lost.
+    pub fn is_better_than(&self, other: &Self) -> bool {
+        (self.stars, other.turns, self.dna, other.parasites_lost)
+            > (other.stars, self.turns, other.dna, self.parasites_lost)
+    }
+}
+
+/// The player's progress through the levels, kept between sessions.
+#[derive(Debug, Clone, Default, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Campaign {
+    /// The best result on each level won, by level id.
+    pub completed: BTreeMap<usize, LevelRecord>,
+    /// Levels that can be played besides the first.
+    pub unlocked: BTreeSet<usize>,
+}
+
+impl Campaign {
+    pub fn is_unlocked(&self, level_id: usize) -> bool {
+        level_id == 0 || self.unlocked.contains(&level_id)
+    }
+
+    pub fn best(&self, level_id: usize) -> Option<&LevelRecord> {
+        self.completed.get(&level_id)
+    }
+
+    /// Records a win and unlocks the level after it. Returns whether it beat
+    /// the previous best.
+    pub fn complete(&mut self, level_id: usize, record: LevelRecord) -> bool {
+        self.unlocked.insert(level_id + 1);
+
+        match self.completed.get(&level_id) {
+            Some(best) if !record.is_better_than(best) => false,
+            _ => {
+                self.completed.insert(level_id, record);
+                true
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("pestilence").join("campaign.ron"))
+    }
+
+    /// Reads the saved profile, or starts a new one if there isn't one yet.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path().ok_or("No user data directory")?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        ron::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No user data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    /// Reads the saved profile, or starts a new one if there isn't one yet.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> Result<Self, String> {
+        match local_storage()?
+            .get_item(STORAGE_KEY)
+            .map_err(|_| "Could not read local storage")?
+        {
+            Some(contents) => ron::from_str(&contents).map_err(|err| err.to_string()),
+            None => Ok(Self::default()),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) -> Result<(), String> {
+        let contents = ron::to_string(self).map_err(|err| err.to_string())?;
+        local_storage()?
+            .set_item(STORAGE_KEY, &contents)
+            .map_err(|_| "Could not write local storage".into())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, String> {
+    web_sys::window()
+        .ok_or("No browser window")?
+        .local_storage()
+        .map_err(|_| "Local storage is not allowed")?
+        .ok_or_else(|| "No local storage".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAR: Par = Par {
+        turns: 5,
+        dna: 2,
+        parasites_lost: 1,
+    };
+
+    fn record(turns: usize, dna: u16, parasites_lost: usize) -> LevelRecord {
+        LevelRecord {
+            turns,
+            dna,
+            parasites_lost,
+            stars: 0,
+        }
+    }
+
+    #[test]
+    fn rate_gives_three_stars_for_meeting_par() {
+        assert_eq!(record(5, 2, 1).rate(&PAR), 3);
+        assert_eq!(record(3, 6, 0).rate(&PAR), 3);
+    }
+
+    #[test]
+    fn rate_gives_two_stars_for_meeting_some_of_par() {
+        assert_eq!(record(6, 2, 1).rate(&PAR), 2);
+        assert_eq!(record(5, 1, 2).rate(&PAR), 2);
+    }
+
+    #[test]
+    fn rate_gives_one_star_for_any_win() {
+        assert_eq!(record(6, 1, 2).rate(&PAR), 1);
+    }
+
+    #[test]
+    fn complete_keeps_the_best_record() {
+        let mut campaign = Campaign::default();
+        let mut good = record(5, 2, 1);
+        good.stars = good.rate(&PAR);
+        let mut worse = record(6, 2, 1);
+        worse.stars = worse.rate(&PAR);
+
+        assert!(campaign.complete(0, worse));
+        assert!(campaign.is_unlocked(1));
+        assert!(campaign.complete(0, good));
+        assert!(!campaign.complete(0, worse));
+        assert_eq!(campaign.best(0), Some(&good));
+    }
+}
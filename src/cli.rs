@@ -0,0 +1,164 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    generate_level, solve, Level, Levels, UnitRegistry, LEVEL_MANIFEST, MAX_ROUNDS, UNIT_REGISTRY,
+};
+
+pub const ASSET_DIR: &str = "assets";
+
+/// Runs the subcommand named on the command line, returning its exit code,
+/// or `None` if the game should start as usual.
+pub fn run(args: &[String]) -> Option<i32> {
+    match args.get(1).map(String::as_str) {
+        Some("validate") => Some(validate(args.get(2))),
+        Some("generate") => Some(generate(args.get(2))),
+        Some("solve") => Some(solve_levels(args.get(2))),
+        _ => None,
+    }
+}
+
+fn manifest_path(manifest: Option<&String>) -> PathBuf {
+    manifest.map_or_else(|| Path::new(ASSET_DIR).join(LEVEL_MANIFEST), PathBuf::from)
+}
+
+fn read_registry() -> Result<UnitRegistry, String> {
+    UnitRegistry::read(Path::new(ASSET_DIR).join(UNIT_REGISTRY))
+}
+
+fn validate(manifest: Option<&String>) -> i32 {
+    match read_registry().and_then(|registry| Levels::read(manifest_path(manifest), &registry)) {
+        Ok(Levels(levels)) => {
+            println!("All {} levels are valid", levels.len());
+            0
+        }
+        Err(problems) => {
+            eprintln!("{}", problems);
+            1
+        }
+    }
+}
+
+fn generate(seed: Option<&String>) -> i32 {
+    let Some(seed) = seed else {
+        eprintln!("Usage: pestilence generate <seed>");
+        return 1;
+    };
+
+    let seed = match seed.parse() {
+        Ok(seed) => seed,
+        Err(err) => {
+            eprintln!("Invalid seed {}: {}", seed, err);
+            return 1;
+        }
+    };
+
+    match read_registry() {
+        Ok(registry) => {
+            print!("{}", generate_level(0, seed, &registry).to_ron());
+            0
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            1
+        }
+    }
+}
+
+/// Solves every level in the manifest and reports the least DNA each was won with.
+/// Fails if any level had no win found with its own `initial_dna`.
+fn solve_levels(manifest: Option<&String>) -> i32 {
+    let (registry, Levels(levels)) = match read_registry().and_then(|registry| {
+        Ok((
+            registry.clone(),
+            Levels::read(manifest_path(manifest), &registry)?,
+        ))
+    }) {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return 1;
+        }
+    };
+
+    report_solutions(&levels, &registry)
+}
+
+/// Prints what the solver found for each level, returning the exit code.
+fn report_solutions(levels: &[Level], registry: &UnitRegistry) -> i32 {
+    let mut unsolved = 0;
+    for level in levels {
+        let report = solve(level, registry, level.id as u64);
+        let Some(solution) = report.minimal else {
+            println!("level {}: no win found in {} rounds", level.id, MAX_ROUNDS);
+            unsolved += 1;
+            continue;
+        };
+
+        if report.winnable {
+            println!(
+                "level {}: winnable, needs {} DNA (has {})",
+                level.id, solution.dna, level.initial_dna
+            );
+        } else {
+            println!(
+                "level {}: no win found in {} rounds with {} DNA, needed {}",
+                level.id, MAX_ROUNDS, level.initial_dna, solution.dna
+            );
+            unsolved += 1;
+        }
+
+        for (turn, actions) in solution.turns().iter().enumerate() {
+            let actions: Vec<_> = actions
+                .iter()
+                .map(|action| format!("{:?}", action))
+                .collect();
+            println!("  turn {}: {}", turn + 1, actions.join(", "));
+        }
+    }
+
+    if unsolved > 0 {
+        eprintln!(
+            "{} of {} levels had no win found with their own DNA",
+            unsolved,
+            levels.len()
+        );
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HumanAi, Par, Position, UnitType};
+
+    fn registry() -> UnitRegistry {
+        UnitRegistry::parse(include_bytes!("../assets/units/archetypes.units.ron")).unwrap()
+    }
+
+    /// A lone Scout, which costs 2 DNA to infect and win with.
+    fn lone_scout(id: usize, initial_dna: u16) -> Level {
+        Level {
+            id,
+            tilemap: vec![vec![1; 3]],
+            units: vec![(UnitType("Scout".into()), Position(1, 0))],
+            obstacles: Vec::new(),
+            initial_dna,
+            ai: HumanAi::default(),
+            par: Par::default(),
+        }
+    }
+
+    #[test]
+    fn solve_succeeds_when_every_level_is_won() {
+        let levels = [lone_scout(0, 2), lone_scout(1, 4)];
+        assert_eq!(report_solutions(&levels, &registry()), 0);
+    }
+
+    #[test]
+    fn solve_fails_when_a_level_cannot_be_won_with_its_own_dna() {
+        let levels = [lone_scout(0, 2), lone_scout(1, 0)];
+        assert_eq!(report_solutions(&levels, &registry()), 1);
+    }
+}
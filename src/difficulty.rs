@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// How hard the humans play.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    /// Greedy humans that now and then wander off instead of closing in.
+    Easy,
+    /// Greedy humans that go after the weakest, most valuable parasites first,
+    /// or the level's own AI if it has one.
+    #[default]
+    Normal,
+    /// The whole squad is planned together with the beam search.
+    Hard,
+}
+
+impl Difficulty {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Easy => "Easy",
+            Self::Normal => "Normal",
+            Self::Hard => "Hard",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Easy => Self::Normal,
+            Self::Normal => Self::Hard,
+            Self::Hard => Self::Easy,
+        }
+    }
+
+    /// Chance in 100 that a human on Easy makes a random move.
+    pub fn blunder_chance(&self) -> u32 {
+        match self {
+            Self::Easy => 25,
+            Self::Normal | Self::Hard => 0,
+        }
+    }
+
+    /// Percentage human health and damage are scaled to when stat scaling is on.
+    pub fn stat_percent(&self) -> u32 {
+        match self {
+            Self::Easy => 75,
+            Self::Normal => 100,
+            Self::Hard => 125,
+        }
+    }
+}
+
+/// The difficulty new games start with. Each game keeps its own copy,
+/// so saves and replays play back the way they were played.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Resource)]
+pub struct DifficultySettings {
+    pub difficulty: Difficulty,
+    /// Whether human `max_health` and `damage` are scaled along with the AI.
+    pub scale_stats: bool,
+}
+
+impl DifficultySettings {
+    /// Scales a human stat, never below 1.
+    pub fn scale(&self, stat: u16) -> u16 {
+        if !self.scale_stats {
+            return stat;
+        }
+        ((stat as u32 * self.difficulty.stat_percent() + 50) / 100).max(1) as u16
+    }
+}
@@ -0,0 +1,190 @@
+use bevy::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    describe_level_errors, HumanAi, Level, Obstacle, Par, Position, Terrain, UnitRegistry, UnitType,
+};
+
+/// Health of breakable walls placed with the editor.
+pub const BREAKABLE_WALL_HEALTH: u16 = 8;
+
+/// Size of the blank level created with Ctrl+N.
+pub const NEW_LEVEL_SIZE: usize = 15;
+
+/// What a click paints onto the level being edited.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Brush {
+    Tile(Terrain),
+    Obstacle(Obstacle),
+    Unit(UnitType),
+    Erase,
+}
+
+impl Brush {
+    pub fn all(registry: &UnitRegistry) -> Vec<Self> {
+        Terrain::ALL
+            .into_iter()
+            .map(Self::Tile)
+            .chain([
+                Self::Obstacle(Obstacle::Wall),
+                Self::Obstacle(Obstacle::BreakableWall {
+                    health: BREAKABLE_WALL_HEALTH,
+                    max_health: BREAKABLE_WALL_HEALTH,
+                }),
+                Self::Obstacle(Obstacle::Boulder),
+            ])
+            .chain(registry.unit_types().map(Self::Unit))
+            .chain([Self::Erase])
+            .collect()
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Self::Tile(terrain) => terrain.name().into(),
+            Self::Obstacle(obstacle) => obstacle.name().into(),
+            Self::Unit(unit_type) => unit_type.name().into(),
+            Self::Erase => "Erase".into(),
+        }
+    }
+}
+
+/// Present while the level editor is open. The board shows `level` instead of a game in progress.
+#[derive(Debug, Clone, Resource)]
+pub struct Editor {
+    pub level: Level,
+    pub brushes: Vec<Brush>,
+    pub brush: usize,
+}
+
+impl Editor {
+    pub fn new(level: Level, registry: &UnitRegistry) -> Self {
+        Self {
+            level,
+            brushes: Brush::all(registry),
+            brush: 1,
+        }
+    }
+
+    /// A square of floor with no units, to be appended after the last level.
+    pub fn blank(id: usize, registry: &UnitRegistry) -> Self {
+        let tilemap = (0..NEW_LEVEL_SIZE)
+            .map(|_| vec![1; NEW_LEVEL_SIZE])
+            .collect();
+
+        Self::new(
+            Level {
+                id,
+                tilemap,
+                units: Vec::new(),
+                obstacles: Vec::new(),
+                initial_dna: 4,
+                ai: HumanAi::default(),
+                par: Par::default(),
+            },
+            registry,
+        )
+    }
+
+    pub fn brush(&self) -> Brush {
+        self.brushes[self.brush].clone()
+    }
+
+    pub fn cycle_brush(&mut self, forward: bool) {
+        let count = self.brushes.len();
+        self.brush = if forward {
+            (self.brush + 1) % count
+        } else {
+            (self.brush + count - 1) % count
+        };
+    }
+
+    /// Applies the current brush to one cell, returning whether the level changed.
+    /// Units and obstacles replace whatever else is on the cell. Painting the void clears it,
+    /// and painting water pushes off any unit.
+    pub fn paint(&mut self, position: Position) -> bool {
+        let Position(col, row) = position;
+        let brush = self.brush();
+        let level = &mut self.level;
+        let terrain = level.terrain(&position);
+        let unit_at = level.units.iter().position(|(_, pos)| *pos == position);
+        let obstacle_at = level.obstacles.iter().position(|(_, pos)| *pos == position);
+
+        match brush {
+            Brush::Tile(new_terrain) => {
+                if terrain == new_terrain {
+                    return false;
+                }
+                level.tilemap[row][col] = new_terrain.tile();
+                if !new_terrain.walkable() {
+                    level.units.retain(|(_, pos)| *pos != position);
+                }
+                if new_terrain == Terrain::Void {
+                    level.obstacles.retain(|(_, pos)| *pos != position);
+                }
+            }
+            Brush::Obstacle(obstacle) => {
+                if obstacle_at.is_some_and(|i| level.obstacles[i].0 == obstacle) {
+                    return false;
+                }
+                level.units.retain(|(_, pos)| *pos != position);
+                level.obstacles.retain(|(_, pos)| *pos != position);
+                level.obstacles.push((obstacle, position));
+            }
+            Brush::Unit(unit_type) => {
+                if !terrain.walkable() || unit_at.is_some_and(|i| level.units[i].0 == unit_type) {
+                    return false;
+                }
+                level.units.retain(|(_, pos)| *pos != position);
+                level.obstacles.retain(|(_, pos)| *pos != position);
+                level.units.push((unit_type, position));
+            }
+            Brush::Erase => {
+                if unit_at.is_none() && obstacle_at.is_none() {
+                    return false;
+                }
+                level.units.retain(|(_, pos)| *pos != position);
+                level.obstacles.retain(|(_, pos)| *pos != position);
+            }
+        }
+
+        true
+    }
+
+    /// Writes the level next to the others and returns where it went.
+    /// A level past the end of the manifest gets a new file, which is appended to the manifest.
+    pub fn save(
+        &self,
+        manifest: impl AsRef<Path>,
+        registry: &UnitRegistry,
+    ) -> Result<PathBuf, String> {
+        if let Err(errors) = self.level.validate(registry) {
+            return Err(describe_level_errors("level", &errors));
+        }
+
+        let manifest = manifest.as_ref();
+        let contents =
+            fs::read(manifest).map_err(|err| format!("{}: {}", manifest.display(), err))?;
+        let mut files: Vec<String> = ron::de::from_bytes(&contents)
+            .map_err(|err| format!("{}: {}", manifest.display(), err))?;
+
+        // Only a level with a manifest entry's id writes over that entry's file
+        let file = match files.get(self.level.id) {
+            Some(file) => file.clone(),
+            None => {
+                let file = format!("level_{}.ron", files.len());
+                files.push(file.clone());
+                let contents =
+                    ron::ser::to_string_pretty(&files, ron::ser::PrettyConfig::default())
+                        .map_err(|err| err.to_string())?;
+                fs::write(manifest, contents + "\n").map_err(|err| err.to_string())?;
+                file
+            }
+        };
+
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+        let path = dir.join(file);
+        fs::write(&path, self.level.to_ron()).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,1412 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::{
+    plan_humans, Difficulty, DifficultySettings, HumanAi, Level, Obstacle, Position,
+    PossibleAttack, Terrain, Turn, Unit, UnitRegistry,
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum GameAction {
+    Infect { id: usize },
+    Move { id: usize, position: Position },
+    Attack { id: usize, target: PossibleAttack },
+    EndTurn,
+}
+
+#[derive(Debug, Clone, Event)]
+pub enum Outcome {
+    UnitInfect {
+        id: usize,
+    },
+    UnitMove {
+        id: usize,
+        start: Position,
+        goal: Position,
+    },
+    UnitAttack {
+        id: usize,
+    },
+    UnitDeath {
+        id: usize,
+    },
+    ObstacleMove {
+        start: Position,
+        goal: Position,
+    },
+    ObstacleDestroyed {
+        position: Position,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Winner {
+    Parasites,
+    Humans,
+}
+
+/// Board state from before a parasite action, restored by undo.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    units: Vec<(Unit, Position)>,
+    obstacles: Vec<(Obstacle, Position)>,
+    dna: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Resource)]
+pub struct GameState {
+    pub level: Level,
+    pub units: Vec<(Unit, Position)>,
+    pub obstacles: Vec<(Obstacle, Position)>,
+    pub dna: u16,
+    pub turn: Turn,
+    pub turn_order: Vec<usize>,
+    pub actions: Vec<GameAction>,
+    #[serde(default)]
+    pub difficulty: DifficultySettings,
+    /// Each human's `max_health` and `damage` from before difficulty scaling, by id,
+    /// given back when it is infected.
+    #[serde(default)]
+    pub unscaled: HashMap<usize, (u16, u16)>,
+    #[serde(skip)]
+    pub history: Vec<Snapshot>,
+}
+
+impl GameState {
+    /// Starts the level. Units whose type is missing from the registry are left out.
+    pub fn new(level: Level, registry: &UnitRegistry) -> Self {
+        let units = level
+            .units
+            .iter()
+            .enumerate()
+            .filter_map(|(id, (unit_type, position))| {
+                Some((registry.unit(id, unit_type)?, *position))
+            })
+            .collect();
+
+        let order = |unit_type| {
+            registry
+                .get(unit_type)
+                .map_or(u16::MAX, |archetype| archetype.order)
+        };
+        let mut turn_order: Vec<_> = level.units.iter().enumerate().collect();
+        turn_order.sort_by(|(_, (unit_a, position_a)), (_, (unit_b, position_b))| {
+            order(unit_a)
+                .cmp(&order(unit_b))
+                .then(position_a.0.cmp(&position_b.0))
+                .then(position_a.1.cmp(&position_b.1))
+        });
+        let turn_order = turn_order.iter().map(|(id, _)| *id).collect();
+
+        Self {
+            obstacles: level.obstacles.clone(),
+            dna: level.initial_dna,
+            turn: Turn::HumansMove,
+            level,
+            units,
+            turn_order,
+            actions: Vec::new(),
+            difficulty: DifficultySettings::default(),
+            unscaled: HashMap::new(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Plays the game at `difficulty`, scaling the humans' stats if it asks for that.
+    pub fn with_difficulty(mut self, difficulty: DifficultySettings) -> Self {
+        for (unit, _) in &mut self.units {
+            if !unit.parasite {
+                self.unscaled
+                    .insert(unit.id, (unit.max_health, unit.damage));
+                unit.max_health = difficulty.scale(unit.max_health);
+                unit.health = difficulty.scale(unit.health);
+                unit.damage = difficulty.scale(unit.damage);
+            }
+        }
+        self.difficulty = difficulty;
+        self
+    }
+
+    pub fn unit(&self, id: usize) -> Option<&(Unit, Position)> {
+        self.units.iter().find(|(unit, _)| unit.id == id)
+    }
+
+    fn unit_mut(&mut self, id: usize) -> Option<&mut (Unit, Position)> {
+        self.units.iter_mut().find(|(unit, _)| unit.id == id)
+    }
+
+    pub fn unit_at(&self, position: &Position) -> Option<&(Unit, Position)> {
+        self.units
+            .iter()
+            .find(|(_, unit_position)| unit_position == position)
+    }
+
+    /// Every action the parasites may take. Empty while the humans are playing.
+    pub fn legal_actions(&self) -> Vec<GameAction> {
+        let mut actions = Vec::new();
+        if self.turn != Turn::Parasites {
+            return actions;
+        }
+
+        for (unit, position) in &self.units {
+            if !unit.parasite {
+                if unit.dna * 2 <= self.dna {
+                    actions.push(GameAction::Infect { id: unit.id });
+                }
+            } else if !unit.has_moved {
+                for movement in
+                    possible_movements(unit, position, &self.level, &self.units, &self.obstacles)
+                {
+                    actions.push(GameAction::Move {
+                        id: unit.id,
+                        position: movement,
+                    });
+                }
+            } else if !unit.has_attacked {
+                let attacks =
+                    possible_attacks(unit, position, &self.level, &self.units, &self.obstacles);
+                for (i, attack_direction) in attacks.iter().enumerate() {
+                    for j in 0..attack_direction.len() {
+                        actions.push(GameAction::Attack {
+                            id: unit.id,
+                            target: PossibleAttack(i, j),
+                        });
+                    }
+                }
+            }
+        }
+
+        actions.push(GameAction::EndTurn);
+        actions
+    }
+
+    /// Whether ending the turn is the only thing left for the parasites to do.
+    pub fn is_turn_over(&self) -> bool {
+        self.turn == Turn::Parasites
+            && self
+                .legal_actions()
+                .iter()
+                .all(|action| *action == GameAction::EndTurn)
+    }
+
+    /// Applies a parasite action, returning what happened or `None` if it is illegal.
+    pub fn apply(&mut self, action: GameAction) -> Option<Vec<Outcome>> {
+        if !self.legal_actions().contains(&action) {
+            return None;
+        }
+
+        self.actions.push(action.clone());
+        self.history.push(Snapshot {
+            units: self.units.clone(),
+            obstacles: self.obstacles.clone(),
+            dna: self.dna,
+        });
+
+        let mut outcomes = Vec::new();
+        match action {
+            GameAction::Infect { id } => {
+                let unscaled = self.unscaled.get(&id).copied();
+                let (unit, _) = self.unit_mut(id)?;
+                let cost = unit.dna * 2;
+                unit.parasite = true;
+                // Parasites don't keep the stats the difficulty gave the humans
+                if let Some((max_health, damage)) = unscaled {
+                    let health = unit.health as u32 * max_health as u32 / unit.max_health as u32;
+                    unit.health = (health as u16).clamp(1, max_health);
+                    unit.max_health = max_health;
+                    unit.damage = damage;
+                }
+                self.dna -= cost;
+                outcomes.push(Outcome::UnitInfect { id });
+            }
+            GameAction::Move { id, position } => {
+                let (unit, unit_position) = self.unit_mut(id)?;
+                let start = *unit_position;
+                *unit_position = position;
+                unit.has_moved = true;
+                outcomes.push(Outcome::UnitMove {
+                    id,
+                    start,
+                    goal: position,
+                });
+
+                let (unit, position) = self.unit(id)?;
+                let attacks =
+                    possible_attacks(unit, position, &self.level, &self.units, &self.obstacles);
+                if attacks
+                    .iter()
+                    .all(|attack_direction| attack_direction.is_empty())
+                {
+                    self.unit_mut(id)?.0.has_attacked = true;
+                }
+            }
+            GameAction::Attack { id, target } => {
+                let (unit, position) = self.unit(id)?.clone();
+                let attacks =
+                    possible_attacks(&unit, &position, &self.level, &self.units, &self.obstacles);
+                let PossibleAttack(i, j) = target;
+                let attack = attacks[i][j];
+                let directions = unit.attack_pattern.direction.vectors();
+                self.unit_mut(id)?.0.has_attacked = true;
+
+                if unit.attack_pattern.charge {
+                    self.charge(id, &attack, &mut outcomes);
+                }
+
+                outcomes.push(Outcome::UnitAttack { id });
+
+                let attack_positions: Vec<_> = if unit.attack_pattern.aoe {
+                    attacks
+                        .iter()
+                        .enumerate()
+                        .filter(|(oi, _)| unit.attack_pattern.all_directions || *oi == i)
+                        .flat_map(|(oi, attack_direction)| {
+                            attack_direction
+                                .iter()
+                                .take(j + 1)
+                                .map(move |attack_position| (oi, *attack_position))
+                        })
+                        .collect()
+                } else {
+                    vec![(i, attack)]
+                };
+
+                for (oi, attack_position) in attack_positions {
+                    let target = if let Some(obstacle) = self.obstacle_at(&attack_position) {
+                        if *obstacle == Obstacle::Boulder {
+                            self.push_boulder(attack_position, directions[oi], &mut outcomes)
+                        } else {
+                            self.hit_obstacle(&attack_position, unit.damage, &mut outcomes);
+                            None
+                        }
+                    } else {
+                        self.hit(&attack_position, unit.damage).inspect(|target| {
+                            outcomes.push(Outcome::UnitDeath { id: target.id });
+                        })
+                    };
+
+                    if let Some(target) = target {
+                        self.dna += target.dna;
+                    }
+                }
+            }
+            GameAction::EndTurn => {
+                self.history.clear();
+                self.turn.next();
+                for (unit, _) in &mut self.units {
+                    unit.has_moved = false;
+                    unit.has_attacked = false;
+                }
+            }
+        }
+
+        Some(outcomes)
+    }
+
+    /// Reverts the last action taken this turn, returning whether there was one.
+    pub fn undo(&mut self) -> bool {
+        let Some(snapshot) = self.history.pop() else {
+            return false;
+        };
+
+        self.units = snapshot.units;
+        self.obstacles = snapshot.obstacles;
+        self.dna = snapshot.dna;
+        self.actions.pop();
+        true
+    }
+
+    /// Plays out the current human phase. Does nothing on the parasites' turn.
+    pub fn advance(&mut self) -> Vec<Outcome> {
+        match self.turn {
+            Turn::Parasites => Vec::new(),
+            Turn::HumansMove => {
+                let mut outcomes = self.terrain_damage();
+                outcomes.extend(self.humans_move());
+                outcomes
+            }
+            Turn::HumansAttack => self.humans_attack(),
+        }
+    }
+
+    pub fn winner(&self) -> Option<Winner> {
+        if self.units.iter().all(|(unit, _)| unit.parasite) {
+            Some(Winner::Parasites)
+        } else if self
+            .units
+            .iter()
+            .all(|(unit, _)| !unit.parasite && unit.dna * 2 > self.dna)
+        {
+            Some(Winner::Humans)
+        } else {
+            None
+        }
+    }
+
+    /// Damages the unit at `position`, removing and returning it if it died.
+    fn hit(&mut self, position: &Position, damage: u16) -> Option<Unit> {
+        let index = self
+            .units
+            .iter()
+            .position(|(_, unit_position)| unit_position == position)?;
+        let (target, _) = &mut self.units[index];
+        target.health = target.health.saturating_sub(damage);
+
+        if target.health == 0 {
+            Some(self.units.remove(index).0)
+        } else {
+            None
+        }
+    }
+
+    fn obstacle_at(&self, position: &Position) -> Option<&Obstacle> {
+        self.obstacles
+            .iter()
+            .find(|(_, obstacle_position)| obstacle_position == position)
+            .map(|(obstacle, _)| obstacle)
+    }
+
+    /// Damages the breakable wall at `position`, knocking it down once its health runs out.
+    fn hit_obstacle(&mut self, position: &Position, damage: u16, outcomes: &mut Vec<Outcome>) {
+        let Some(index) = self
+            .obstacles
+            .iter()
+            .position(|(_, obstacle_position)| obstacle_position == position)
+        else {
+            return;
+        };
+        let Obstacle::BreakableWall { health, .. } = &mut self.obstacles[index].0 else {
+            return;
+        };
+
+        *health = health.saturating_sub(damage);
+        if *health == 0 {
+            self.obstacles.remove(index);
+            outcomes.push(Outcome::ObstacleDestroyed {
+                position: *position,
+            });
+        }
+    }
+
+    /// Moves a charging unit up to the tile in front of `target`.
+    fn charge(&mut self, id: usize, target: &Position, outcomes: &mut Vec<Outcome>) {
+        let Some((_, position)) = self.unit_mut(id) else {
+            return;
+        };
+        let start = *position;
+        *position = charge(&start, target);
+        outcomes.push(Outcome::UnitMove {
+            id,
+            start,
+            goal: *position,
+        });
+    }
+
+    /// Slides the boulder at `position` along `direction` until the void or another obstacle
+    /// stops it. A unit in its way is crushed and the boulder comes to rest in its place.
+    fn push_boulder(
+        &mut self,
+        position: Position,
+        direction: (isize, isize),
+        outcomes: &mut Vec<Outcome>,
+    ) -> Option<Unit> {
+        let index = self
+            .obstacles
+            .iter()
+            .position(|(_, obstacle_position)| *obstacle_position == position)?;
+        let (width, height) = self.level.dimensions();
+
+        let mut goal = position;
+        let mut crushed = None;
+        loop {
+            let Position(col, row) = goal;
+            let (col, row) = (col as isize + direction.0, row as isize + direction.1);
+            if col < 0 || row < 0 || col as usize >= width || row as usize >= height {
+                break;
+            }
+
+            let next = Position(col as usize, row as usize);
+            if !self.level.terrain(&next).walkable()
+                || self
+                    .obstacles
+                    .iter()
+                    .any(|(_, obstacle_position)| *obstacle_position == next)
+            {
+                break;
+            }
+
+            goal = next;
+            if let Some(unit_index) = self
+                .units
+                .iter()
+                .position(|(_, unit_position)| *unit_position == next)
+            {
+                crushed = Some(self.units.remove(unit_index).0);
+                break;
+            }
+        }
+
+        if goal != position {
+            self.obstacles[index].1 = goal;
+            outcomes.push(Outcome::ObstacleMove {
+                start: position,
+                goal,
+            });
+        }
+        if let Some(unit) = &crushed {
+            outcomes.push(Outcome::UnitDeath { id: unit.id });
+        }
+
+        crushed
+    }
+
+    /// Hurts every unit standing on damaging terrain as the parasites' turn ends.
+    fn terrain_damage(&mut self) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        let hazards: Vec<_> = self
+            .units
+            .iter()
+            .map(|(_, position)| (*position, self.level.terrain(position).damage_per_turn()))
+            .filter(|(_, damage)| *damage > 0)
+            .collect();
+
+        for (position, damage) in hazards {
+            if let Some(unit) = self.hit(&position, damage) {
+                outcomes.push(Outcome::UnitDeath { id: unit.id });
+            }
+        }
+
+        outcomes
+    }
+
+    fn humans_move(&mut self) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+
+        if !self.units.iter().any(|(unit, _)| unit.parasite) {
+            self.turn.next();
+            return outcomes;
+        }
+
+        let ai = match self.difficulty.difficulty {
+            Difficulty::Easy => HumanAi::Greedy,
+            Difficulty::Normal => self.level.ai,
+            Difficulty::Hard => HumanAi::BeamSearch,
+        };
+        let prioritize = self.difficulty.difficulty != Difficulty::Easy;
+
+        if ai == HumanAi::BeamSearch {
+            let planned = plan_humans(self);
+            for id in &self.turn_order {
+                let (Some((_, start)), Some((_, goal))) = (
+                    self.unit(*id),
+                    planned.iter().find(|(unit, _)| unit.id == *id),
+                ) else {
+                    continue;
+                };
+                if start != goal {
+                    outcomes.push(Outcome::UnitMove {
+                        id: *id,
+                        start: *start,
+                        goal: *goal,
+                    });
+                }
+            }
+
+            self.units = planned;
+            self.turn.next();
+            return outcomes;
+        }
+
+        for id in self.turn_order.clone() {
+            let Some((unit, position)) = self.unit(id).cloned() else {
+                continue;
+            };
+            if unit.parasite {
+                continue;
+            }
+
+            // Seeded from the game so replays and undo see the same blunders
+            let mut rng = StdRng::seed_from_u64((self.actions.len() as u64) << 32 | id as u64);
+            if rng.gen_range(0..100) < self.difficulty.difficulty.blunder_chance() {
+                let movements =
+                    possible_movements(&unit, &position, &self.level, &self.units, &self.obstacles);
+                if let Some(goal) = movements.choose(&mut rng) {
+                    outcomes.push(Outcome::UnitMove {
+                        id,
+                        start: position,
+                        goal: *goal,
+                    });
+                    self.unit_mut(id).unwrap().1 = *goal;
+                }
+                continue;
+            }
+
+            let mut targets: Vec<_> = self
+                .units
+                .iter()
+                .filter(|(target, _)| target.parasite)
+                .collect();
+            if prioritize {
+                targets.sort_by_key(|(target, _)| Reverse(target_priority(target)));
+            }
+
+            // Going after a lower priority target costs one extra move per rank
+            let attack_positions: Vec<_> = targets
+                .iter()
+                .enumerate()
+                .flat_map(|(rank, (_, target_position))| {
+                    let detour = if prioritize { rank as u16 } else { 0 };
+                    longest_range_attacks(
+                        &unit,
+                        target_position,
+                        &self.level,
+                        &self.units,
+                        &self.obstacles,
+                    )
+                    .into_iter()
+                    .map(move |direction| (detour, direction))
+                })
+                .collect();
+
+            let mut nearest_attack_position = None;
+            let mut smallest_cost = u16::MAX;
+            for (detour, direction) in attack_positions {
+                for attack_position in direction {
+                    let path = pathfind(
+                        &unit,
+                        &position,
+                        &attack_position,
+                        &self.level,
+                        &self.units,
+                        &self.obstacles,
+                    );
+                    if let Some((path, cost)) = path {
+                        let cost = cost.saturating_add(detour);
+                        if !path.is_empty() && cost < smallest_cost {
+                            nearest_attack_position = Some(path[0]);
+                            smallest_cost = cost;
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if let Some(goal) = nearest_attack_position {
+                outcomes.push(Outcome::UnitMove {
+                    id,
+                    start: position,
+                    goal,
+                });
+                self.unit_mut(id).unwrap().1 = goal;
+            }
+        }
+
+        let units = self.units.clone();
+        for (unit, position) in &mut self.units {
+            if unit.parasite {
+                continue;
+            }
+
+            unit.attack_directions = telegraph_attack(
+                unit,
+                position,
+                &self.level,
+                &units,
+                &self.obstacles,
+                prioritize,
+            );
+        }
+
+        self.turn.next();
+        outcomes
+    }
+
+    fn humans_attack(&mut self) -> Vec<Outcome> {
+        let mut outcomes = Vec::new();
+        let (width, height) = self.level.dimensions();
+
+        for id in self.turn_order.clone() {
+            let Some((unit, position)) = self.unit(id).cloned() else {
+                continue;
+            };
+            if unit.parasite {
+                continue;
+            }
+
+            if let Some(attack_directions) = &unit.attack_directions {
+                outcomes.push(Outcome::UnitAttack { id });
+
+                for direction in attack_directions {
+                    for dist in 1..=attack_range(&unit, &position, &self.level) {
+                        let dcol = direction.0 * dist as isize;
+                        let drow = direction.1 * dist as isize;
+                        let Position(col, row) = position;
+
+                        if -dcol > col as isize || -drow > row as isize {
+                            break;
+                        }
+
+                        let attack = Position(
+                            (col as isize + dcol) as usize,
+                            (row as isize + drow) as usize,
+                        );
+                        let Position(col, row) = attack;
+
+                        if col >= width
+                            || row >= height
+                            || self.level.terrain(&attack).blocks_shots()
+                        {
+                            break;
+                        } else if self
+                            .obstacles
+                            .iter()
+                            .any(|(_, position)| *position == attack)
+                        {
+                            match self.obstacle_at(&attack) {
+                                Some(Obstacle::Boulder)
+                                    if unit.attack_pattern.pushes_boulders() =>
+                                {
+                                    self.push_boulder(attack, *direction, &mut outcomes);
+                                    if unit.attack_pattern.charge {
+                                        self.charge(id, &attack, &mut outcomes);
+                                    }
+                                }
+                                Some(Obstacle::BreakableWall { .. }) if unit.attack_pattern.aoe => {
+                                    self.hit_obstacle(&attack, unit.damage, &mut outcomes);
+                                }
+                                _ => {}
+                            }
+                            break;
+                        } else if self.unit_at(&attack).is_some() {
+                            if let Some(target) = self.hit(&attack, unit.damage) {
+                                outcomes.push(Outcome::UnitDeath { id: target.id });
+                            }
+
+                            if unit.attack_pattern.charge {
+                                self.charge(id, &attack, &mut outcomes);
+                            }
+
+                            if !unit.attack_pattern.aoe {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        self.turn.next();
+        outcomes
+    }
+}
+
+/// The tile next to `target` that a charging unit at `position` ends up on.
+fn charge(position: &Position, target: &Position) -> Position {
+    let Position(col, row) = *position;
+    let Position(target_col, target_row) = *target;
+    let mut goal = *position;
+
+    if col < target_col {
+        goal.0 = target_col - 1;
+    } else if col > target_col {
+        goal.0 = target_col + 1;
+    }
+    if row < target_row {
+        goal.1 = target_row - 1;
+    } else if row > target_row {
+        goal.1 = target_row + 1;
+    }
+
+    goal
+}
+
+/// How far a unit at `position` can attack, counting the terrain it stands on.
+pub fn attack_range(unit: &Unit, position: &Position, level: &Level) -> u16 {
+    unit.range + level.terrain(position).range_bonus()
+}
+
+/// How much a human wants to go after a parasite: valuable and nearly dead ones first.
+fn target_priority(target: &Unit) -> u32 {
+    target.dna as u32 * 100 / target.health.max(1) as u32
+}
+
+/// Picks the directions a human will attack in on its next attack phase.
+/// With `prioritize`, a parasite in sight is chosen by `target_priority` rather than
+/// by the order the directions are checked in.
+fn telegraph_attack(
+    unit: &Unit,
+    position: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+    prioritize: bool,
+) -> Option<Vec<(isize, isize)>> {
+    if unit.attack_pattern.all_directions {
+        return Some(unit.attack_pattern.direction.vectors());
+    }
+
+    let mut best_target: Option<((isize, isize), u32)> = None;
+    let mut directions = Vec::new();
+    for direction in unit.attack_pattern.direction.vectors() {
+        let mut is_valid = true;
+        'b: for dist in 1..=attack_range(unit, position, level) {
+            let dcol = direction.0 * dist as isize;
+            let drow = direction.1 * dist as isize;
+            let Position(col, row) = *position;
+
+            if -dcol > col as isize || -drow > row as isize {
+                break 'b;
+            }
+
+            let attack = Position(
+                (col as isize + dcol) as usize,
+                (row as isize + drow) as usize,
+            );
+
+            if level.terrain(&attack).blocks_shots() {
+                break 'b;
+            } else if let Some((obstacle, _)) =
+                obstacles.iter().find(|(_, position)| *position == attack)
+            {
+                // An adjacent boulder blocks the attack unless the unit can push it
+                let pushable =
+                    *obstacle == Obstacle::Boulder && unit.attack_pattern.pushes_boulders();
+                if dist == 1 && !pushable {
+                    is_valid = false;
+                }
+                break 'b;
+            } else if let Some((target, _)) = units.iter().find(|(_, position)| *position == attack)
+            {
+                if target.parasite && prioritize {
+                    let priority = target_priority(target);
+                    let outranked = best_target.is_some_and(|(_, best)| best >= priority);
+                    if !outranked {
+                        best_target = Some((direction, priority));
+                    }
+                    break 'b;
+                } else if target.parasite {
+                    return Some(vec![direction]);
+                } else {
+                    is_valid = false;
+                    break 'b;
+                }
+            }
+        }
+        if is_valid {
+            directions.push(direction);
+        }
+    }
+
+    if let Some((direction, _)) = best_target {
+        return Some(vec![direction]);
+    }
+    if directions.is_empty() {
+        return None;
+    }
+
+    directions.sort_by(|(dcol_a, drow_a), (dcol_b, drow_b)| {
+        let mut a_score = 0;
+        let mut b_score = 0;
+        for (target, target_position) in units {
+            let Position(col, row) = *position;
+            let Position(target_col, target_row) = target_position;
+            if target.parasite {
+                if (*target_col < col && *dcol_a == -1)
+                    || (*target_col > col && *dcol_a == 1)
+                    || (*target_col == col && *dcol_a == 0)
+                {
+                    a_score += 1;
+                }
+                if (*target_row < row && *drow_a == -1)
+                    || (*target_row > row && *drow_a == 1)
+                    || (*target_row == row && *drow_a == 0)
+                {
+                    a_score += 1;
+                }
+
+                if (*target_col < col && *dcol_b == -1)
+                    || (*target_col > col && *dcol_b == 1)
+                    || (*target_col == col && *dcol_b == 0)
+                {
+                    b_score += 1;
+                }
+                if (*target_row < row && *drow_b == -1)
+                    || (*target_row > row && *drow_b == 1)
+                    || (*target_row == row && *drow_b == 0)
+                {
+                    b_score += 1;
+                }
+            }
+        }
+        a_score.cmp(&b_score).reverse()
+    });
+    Some(vec![directions[0]])
+}
+
+pub fn possible_movements(
+    unit: &Unit,
+    position: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+) -> Vec<Position> {
+    let mut movements = Vec::new();
+    for direction in unit.move_direction.vectors() {
+        let mut spent = 0;
+        for dist in 1..=unit.speed {
+            let dcol = direction.0 * dist as isize;
+            let drow = direction.1 * dist as isize;
+            let Position(col, row) = position;
+
+            if -dcol > *col as isize || -drow > *row as isize {
+                break;
+            }
+
+            let movement = Position(
+                (*col as isize + dcol) as usize,
+                (*row as isize + drow) as usize,
+            );
+            let Position(col, row) = movement;
+
+            let width = level.tilemap[0].len();
+            let height = level.tilemap.len();
+
+            if col >= width
+                || row >= height
+                || !level.terrain(&movement).walkable()
+                || units.iter().any(|(_, position)| *position == movement)
+                || obstacles.iter().any(|(_, position)| *position == movement)
+            {
+                break;
+            }
+
+            spent += level.terrain(&movement).movement_cost();
+            if spent > unit.speed {
+                break;
+            }
+            movements.push(movement);
+        }
+    }
+    movements
+}
+
+pub fn possible_attacks(
+    unit: &Unit,
+    position: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+) -> Vec<Vec<Position>> {
+    let mut attacks = Vec::new();
+    let width = level.tilemap[0].len();
+    let height = level.tilemap.len();
+
+    for direction in unit.attack_pattern.direction.vectors() {
+        let mut attack_direction = Vec::new();
+        for dist in 1..=attack_range(unit, position, level) {
+            let dcol = direction.0 * dist as isize;
+            let drow = direction.1 * dist as isize;
+            let Position(col, row) = position;
+
+            if -dcol > *col as isize || -drow > *row as isize {
+                break;
+            }
+
+            let attack = Position(
+                (*col as isize + dcol) as usize,
+                (*row as isize + drow) as usize,
+            );
+            let Position(col, row) = attack;
+
+            if col >= width || row >= height || level.terrain(&attack).blocks_shots() {
+                break;
+            } else if let Some((obstacle, _)) =
+                obstacles.iter().find(|(_, position)| *position == attack)
+            {
+                match obstacle {
+                    Obstacle::Boulder if unit.attack_pattern.pushes_boulders() => {
+                        attack_direction.push(attack);
+                    }
+                    Obstacle::BreakableWall { .. } if unit.attack_pattern.aoe => {
+                        attack_direction.push(attack);
+                    }
+                    _ => {}
+                }
+                break;
+            } else if units.iter().any(|(_, position)| *position == attack) {
+                attack_direction.push(attack);
+                if !unit.attack_pattern.aoe {
+                    break;
+                }
+            }
+        }
+        attacks.push(attack_direction);
+    }
+
+    attacks
+}
+
+pub fn attack_positions(
+    unit: &Unit,
+    direction: (isize, isize),
+    position: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+) -> Vec<Position> {
+    let mut positions = Vec::new();
+    let width = level.tilemap[0].len();
+    let height = level.tilemap.len();
+
+    for dist in 1..=attack_range(unit, position, level) {
+        let dcol = direction.0 * dist as isize;
+        let drow = direction.1 * dist as isize;
+        let Position(col, row) = position;
+
+        if -dcol > *col as isize || -drow > *row as isize {
+            break;
+        }
+
+        let attack_position = Position(
+            (*col as isize + dcol) as usize,
+            (*row as isize + drow) as usize,
+        );
+        let Position(col, row) = attack_position;
+
+        if col >= width
+            || row >= height
+            || level.terrain(&attack_position).blocks_shots()
+            || obstacles
+                .iter()
+                .any(|(_, position)| *position == attack_position)
+        {
+            break;
+        } else if units
+            .iter()
+            .any(|(_, position)| *position == attack_position)
+        {
+            positions.push(attack_position);
+            if !unit.attack_pattern.aoe {
+                break;
+            }
+        } else {
+            positions.push(attack_position);
+        }
+    }
+
+    positions
+}
+
+pub fn longest_range_attacks(
+    unit: &Unit,
+    target_position: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+) -> Vec<Vec<Position>> {
+    let mut attacks = Vec::new();
+    let width = level.tilemap[0].len();
+    let height = level.tilemap.len();
+
+    for direction in unit.attack_pattern.direction.vectors() {
+        let mut direction_attacks = Vec::new();
+        for dist in 1..=unit.range + Terrain::max_range_bonus() {
+            let dcol = direction.0 * dist as isize;
+            let drow = direction.1 * dist as isize;
+            let Position(col, row) = target_position;
+
+            if -dcol > *col as isize || -drow > *row as isize {
+                break;
+            }
+
+            let attack = Position(
+                (*col as isize + dcol) as usize,
+                (*row as isize + drow) as usize,
+            );
+            let Position(col, row) = attack;
+
+            if col >= width
+                || row >= height
+                || level.terrain(&attack).blocks_shots()
+                || units
+                    .iter()
+                    .any(|(other_unit, position)| other_unit.id != unit.id && *position == attack)
+                || obstacles.iter().any(|(_, position)| *position == attack)
+            {
+                break;
+            } else if level.terrain(&attack).walkable()
+                && dist <= attack_range(unit, &attack, level)
+            {
+                direction_attacks.push(attack);
+            }
+        }
+        attacks.push(direction_attacks.iter().rev().copied().collect());
+    }
+
+    attacks
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Frontier {
+    priority: u16,
+    position: Position,
+}
+
+impl Ord for Frontier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.cmp(&self.priority).then_with(|| {
+            [self.position.0, self.position.1].cmp(&[other.position.0, other.position.1])
+        })
+    }
+}
+
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A* algorithm
+pub fn pathfind(
+    unit: &Unit,
+    start: &Position,
+    goal: &Position,
+    level: &Level,
+    units: &[(Unit, Position)],
+    obstacles: &[(Obstacle, Position)],
+) -> Option<(Vec<Position>, u16)> {
+    let mut frontier = BinaryHeap::new();
+    let mut came_from = HashMap::new();
+    let mut costs = HashMap::new();
+
+    frontier.push(Frontier {
+        priority: 0,
+        position: *start,
+    });
+    costs.insert(*start, 0);
+
+    while let Some(Frontier {
+        priority: _,
+        position,
+    }) = frontier.pop()
+    {
+        if position == *goal {
+            break;
+        }
+
+        let units: Vec<_> = units
+            .iter()
+            .map(|(other_unit, other_position)| {
+                if other_unit.id == unit.id {
+                    (other_unit.clone(), position)
+                } else {
+                    (other_unit.clone(), *other_position)
+                }
+            })
+            .collect();
+
+        for movement in possible_movements(unit, &position, level, &units, obstacles) {
+            let terrain = level.terrain(&movement);
+            let new_cost =
+                costs.get(&position).unwrap() + terrain.movement_cost() + terrain.damage_per_turn();
+            if !costs.contains_key(&movement) || new_cost < *costs.get(&movement).unwrap() {
+                frontier.push(Frontier {
+                    priority: new_cost + distance(goal, &movement),
+                    position: movement,
+                });
+                came_from.insert(movement, Some(position));
+                costs.insert(movement, new_cost);
+            }
+        }
+    }
+
+    let mut position = goal;
+    let mut path = Vec::new();
+
+    while position != start {
+        path.push(*position);
+        position = match came_from.get(position) {
+            Some(Some(position)) => position,
+            _ => return None,
+        };
+    }
+
+    path.reverse();
+
+    Some((path, *costs.get(goal).unwrap()))
+}
+
+pub fn distance(a: &Position, b: &Position) -> u16 {
+    let Position(acol, arow) = a;
+    let Position(bcol, brow) = b;
+    let col_diff = (*acol as f32 - *bcol as f32).abs();
+    let row_diff = (*arow as f32 - *brow as f32).abs();
+    (col_diff * col_diff + row_diff * row_diff).sqrt() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HumanAi, Par, UnitType};
+
+    fn registry() -> UnitRegistry {
+        UnitRegistry::parse(include_bytes!("../assets/units/archetypes.units.ron")).unwrap()
+    }
+
+    /// A single row of floor, with the units and obstacles placed along it.
+    fn corridor(
+        units: &[(&str, usize)],
+        obstacles: &[(Obstacle, usize)],
+        initial_dna: u16,
+    ) -> Level {
+        Level {
+            id: 0,
+            tilemap: vec![vec![1; 8]],
+            units: units
+                .iter()
+                .map(|(name, col)| (UnitType(name.to_string()), Position(*col, 0)))
+                .collect(),
+            obstacles: obstacles
+                .iter()
+                .map(|(obstacle, col)| (obstacle.clone(), Position(*col, 0)))
+                .collect(),
+            initial_dna,
+            ai: HumanAi::default(),
+            par: Par::default(),
+        }
+    }
+
+    /// The level on the parasites' turn, with a Heavy already infected and moved at the
+    /// west end, ready to attack.
+    fn heavy_game(units: &[(&str, usize)], obstacles: &[(Obstacle, usize)]) -> GameState {
+        let mut level_units = vec![("Heavy", 0)];
+        level_units.extend_from_slice(units);
+        let mut game = GameState::new(corridor(&level_units, obstacles, 6), &registry());
+        game.turn = Turn::Parasites;
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+        game.unit_mut(0).unwrap().0.has_moved = true;
+        game
+    }
+
+    #[test]
+    fn legal_actions_wait_for_the_parasites_turn() {
+        let mut game = GameState::new(
+            corridor(&[("Scout", 0), ("Assault", 4)], &[], 2),
+            &registry(),
+        );
+        assert!(game.legal_actions().is_empty());
+
+        game.turn = Turn::Parasites;
+        // Only the Scout is cheap enough to infect
+        assert_eq!(
+            game.legal_actions(),
+            vec![GameAction::Infect { id: 0 }, GameAction::EndTurn]
+        );
+    }
+
+    #[test]
+    fn apply_infects_and_rejects_illegal_actions() {
+        let mut game = GameState::new(
+            corridor(&[("Scout", 0), ("Assault", 4)], &[], 2),
+            &registry(),
+        );
+        game.turn = Turn::Parasites;
+
+        assert!(game.apply(GameAction::Infect { id: 1 }).is_none());
+        assert!(game.actions.is_empty());
+
+        assert!(game.apply(GameAction::Infect { id: 0 }).is_some());
+        assert!(game.unit(0).unwrap().0.parasite);
+        assert_eq!(game.dna, 0);
+        assert_eq!(game.actions, vec![GameAction::Infect { id: 0 }]);
+    }
+
+    #[test]
+    fn apply_moves_a_parasite_once() {
+        let mut game = GameState::new(corridor(&[("Assault", 0)], &[], 4), &registry());
+        game.turn = Turn::Parasites;
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+
+        let movement = GameAction::Move {
+            id: 0,
+            position: Position(2, 0),
+        };
+        assert!(game.legal_actions().contains(&movement));
+        game.apply(movement.clone()).unwrap();
+        assert_eq!(game.unit(0).unwrap().1, Position(2, 0));
+        assert!(game.apply(movement).is_none());
+    }
+
+    #[test]
+    fn undo_reverts_actions_until_the_turn_ends() {
+        let mut game = GameState::new(
+            corridor(&[("Assault", 0), ("Scout", 6)], &[], 6),
+            &registry(),
+        );
+        game.turn = Turn::Parasites;
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+        game.apply(GameAction::Move {
+            id: 0,
+            position: Position(1, 0),
+        })
+        .unwrap();
+
+        assert!(game.undo());
+        assert_eq!(game.unit(0).unwrap().1, Position(0, 0));
+        assert!(!game.unit(0).unwrap().0.has_moved);
+        assert_eq!(game.actions.len(), 1);
+
+        assert!(game.undo());
+        assert!(!game.unit(0).unwrap().0.parasite);
+        assert_eq!(game.dna, 6);
+        assert!(game.actions.is_empty());
+        assert!(!game.undo());
+
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+        game.apply(GameAction::EndTurn).unwrap();
+        assert!(!game.undo());
+        assert!(game.unit(0).unwrap().0.parasite);
+    }
+
+    #[test]
+    fn advance_plays_the_human_phases_in_order() {
+        let mut game = GameState::new(
+            corridor(&[("Assault", 0), ("Scout", 3)], &[], 2),
+            &registry(),
+        );
+        assert_eq!(game.turn, Turn::HumansMove);
+        assert!(game.advance().is_empty());
+        assert_eq!(game.turn, Turn::Parasites);
+
+        // Nothing happens on the parasites' turn until they end it
+        assert!(game.advance().is_empty());
+        assert_eq!(game.turn, Turn::Parasites);
+
+        game.apply(GameAction::Infect { id: 1 }).unwrap();
+        game.apply(GameAction::EndTurn).unwrap();
+        assert_eq!(game.turn, Turn::HumansAttack);
+        game.advance();
+        assert_eq!(game.turn, Turn::HumansMove);
+
+        // The Assault lines up a shot at the infected Scout, and takes it once the turn ends
+        game.advance();
+        assert_eq!(game.turn, Turn::Parasites);
+        assert!(game.unit(0).unwrap().0.attack_directions.is_some());
+
+        game.apply(GameAction::EndTurn).unwrap();
+        let outcomes = game.advance();
+        assert!(outcomes
+            .iter()
+            .any(|outcome| matches!(outcome, Outcome::UnitDeath { id: 1 })));
+        assert!(game.unit(1).is_none());
+    }
+
+    #[test]
+    fn winner_is_decided_by_infections_and_dna() {
+        let mut game = GameState::new(
+            corridor(&[("Scout", 0), ("Assault", 4)], &[], 2),
+            &registry(),
+        );
+        assert_eq!(game.winner(), None);
+
+        game.turn = Turn::Parasites;
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+        // The Scout is still a parasite, so the humans haven't won yet
+        assert_eq!(game.winner(), None);
+
+        game.units.retain(|(unit, _)| unit.id != 0);
+        assert_eq!(game.winner(), Some(Winner::Humans));
+
+        game.units
+            .iter_mut()
+            .for_each(|(unit, _)| unit.parasite = true);
+        assert_eq!(game.winner(), Some(Winner::Parasites));
+    }
+
+    #[test]
+    fn boulders_slide_until_blocked() {
+        let mut game = heavy_game(&[], &[(Obstacle::Boulder, 1), (Obstacle::Wall, 5)]);
+        let outcomes = game
+            .apply(GameAction::Attack {
+                id: 0,
+                target: PossibleAttack(0, 0),
+            })
+            .unwrap();
+
+        assert_eq!(game.obstacles[0], (Obstacle::Boulder, Position(4, 0)));
+        assert!(outcomes.iter().any(|outcome| matches!(
+            outcome,
+            Outcome::ObstacleMove {
+                start: Position(1, 0),
+                goal: Position(4, 0),
+            }
+        )));
+    }
+
+    #[test]
+    fn boulders_crush_the_first_unit_in_their_way() {
+        let mut game = heavy_game(&[("Scout", 3), ("Scout", 5)], &[(Obstacle::Boulder, 1)]);
+        let dna = game.dna;
+        game.apply(GameAction::Attack {
+            id: 0,
+            target: PossibleAttack(0, 0),
+        })
+        .unwrap();
+
+        assert_eq!(game.obstacles[0], (Obstacle::Boulder, Position(3, 0)));
+        assert!(game.unit(1).is_none());
+        assert!(game.unit(2).is_some());
+        assert_eq!(game.dna, dna + 1);
+    }
+
+    #[test]
+    fn only_boulder_pushers_can_target_boulders() {
+        let mut game = GameState::new(
+            corridor(&[("Assault", 0)], &[(Obstacle::Boulder, 2)], 4),
+            &registry(),
+        );
+        game.turn = Turn::Parasites;
+        game.apply(GameAction::Infect { id: 0 }).unwrap();
+        game.unit_mut(0).unwrap().0.has_moved = true;
+
+        assert_eq!(game.legal_actions(), vec![GameAction::EndTurn]);
+    }
+
+    #[test]
+    fn breakable_walls_wear_down_and_fall() {
+        let wall = Obstacle::BreakableWall {
+            health: 6,
+            max_health: 6,
+        };
+        let mut game = heavy_game(&[], &[(wall, 1)]);
+        let attack = GameAction::Attack {
+            id: 0,
+            target: PossibleAttack(0, 0),
+        };
+
+        game.apply(attack.clone()).unwrap();
+        assert_eq!(
+            game.obstacles,
+            vec![(
+                Obstacle::BreakableWall {
+                    health: 2,
+                    max_health: 6
+                },
+                Position(1, 0)
+            )]
+        );
+
+        game.unit_mut(0).unwrap().0.has_attacked = false;
+        let outcomes = game.apply(attack).unwrap();
+        assert!(game.obstacles.is_empty());
+        assert!(outcomes.iter().any(|outcome| matches!(
+            outcome,
+            Outcome::ObstacleDestroyed {
+                position: Position(1, 0)
+            }
+        )));
+    }
+}
@@ -0,0 +1,303 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    dna_range, least_dna, sample_action, GameState, HumanAi, Level, Obstacle, Par, Position, Turn,
+    UnitRegistry, UnitType, Winner,
+};
+
+pub const MIN_SIZE: usize = 13;
+pub const MAX_SIZE: usize = 21;
+
+/// Chance a cell starts out walkable before the tilemap is smoothed.
+const FILL_CHANCE: f64 = 0.58;
+const SMOOTHING_STEPS: usize = 5;
+/// The largest walkable region must cover at least this share of the tilemap.
+const MIN_COVERAGE: f64 = 0.4;
+
+/// One wall for roughly this many walkable tiles.
+const TILES_PER_WALL: usize = 14;
+/// The squad's total DNA is drawn between these many walkable tiles per point.
+const TILES_PER_DNA: (usize, usize) = (10, 6);
+
+/// Random playouts tried at each DNA amount before giving the parasites more.
+const PLAYOUTS: usize = 12;
+const PLAYOUT_STEPS: usize = 400;
+
+/// Builds a level from `seed`. The same seed always gives the same level.
+pub fn generate_level(id: usize, seed: u64, registry: &UnitRegistry) -> Level {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let width = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+    let height = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+    let (tilemap, region) = organic_tilemap(&mut rng, width, height);
+
+    let mut free = region.clone();
+    free.shuffle(&mut rng);
+
+    let obstacles = place_walls(&mut rng, &region, &mut free);
+    let units = draw_squad(&mut rng, &region, &mut free, registry);
+
+    let mut level = Level {
+        id,
+        tilemap,
+        units,
+        obstacles,
+        initial_dna: 0,
+        ai: HumanAi::default(),
+        par: Par::default(),
+    };
+    level.initial_dna = tune_initial_dna(&mut rng, &level, registry);
+    level
+}
+
+/// Smooths random noise into cave-like blobs and keeps the largest connected one,
+/// returning the tilemap and its walkable positions.
+fn organic_tilemap(
+    rng: &mut StdRng,
+    width: usize,
+    height: usize,
+) -> (Vec<Vec<usize>>, Vec<Position>) {
+    loop {
+        let mut walkable: Vec<Vec<bool>> = (0..height)
+            .map(|_| (0..width).map(|_| rng.gen_bool(FILL_CHANCE)).collect())
+            .collect();
+
+        for _ in 0..SMOOTHING_STEPS {
+            walkable = (0..height)
+                .map(|row| {
+                    (0..width)
+                        .map(|col| neighbourhood(&walkable, col, row) >= 5)
+                        .collect()
+                })
+                .collect();
+        }
+
+        let region = largest_region(&walkable);
+        if (region.len() as f64) < (width * height) as f64 * MIN_COVERAGE {
+            continue;
+        }
+
+        let mut tilemap = vec![vec![0; width]; height];
+        for Position(col, row) in &region {
+            tilemap[*row][*col] = 1;
+        }
+        return (tilemap, region);
+    }
+}
+
+/// Walkable cells in the 3x3 square around a cell, counting the cell itself.
+fn neighbourhood(walkable: &[Vec<bool>], col: usize, row: usize) -> usize {
+    let mut count = 0;
+    for drow in -1..=1 {
+        for dcol in -1..=1 {
+            let (ncol, nrow) = (col as isize + dcol, row as isize + drow);
+            if ncol >= 0
+                && nrow >= 0
+                && walkable
+                    .get(nrow as usize)
+                    .and_then(|tiles| tiles.get(ncol as usize))
+                    .is_some_and(|tile| *tile)
+            {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+/// Cells reachable from `start` in cardinal steps.
+fn flood(walkable: &[Vec<bool>], start: Position, visited: &mut [Vec<bool>]) -> Vec<Position> {
+    let mut region = Vec::new();
+    let mut stack = vec![start];
+    visited[start.1][start.0] = true;
+
+    while let Some(position) = stack.pop() {
+        region.push(position);
+        let Position(col, row) = position;
+        for (dcol, drow) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (ncol, nrow) = (col as isize + dcol, row as isize + drow);
+            if ncol < 0 || nrow < 0 {
+                continue;
+            }
+            let (ncol, nrow) = (ncol as usize, nrow as usize);
+            if walkable
+                .get(nrow)
+                .and_then(|tiles| tiles.get(ncol))
+                .is_some_and(|tile| *tile)
+                && !visited[nrow][ncol]
+            {
+                visited[nrow][ncol] = true;
+                stack.push(Position(ncol, nrow));
+            }
+        }
+    }
+
+    region.sort_by_key(|Position(col, row)| (*row, *col));
+    region
+}
+
+fn largest_region(walkable: &[Vec<bool>]) -> Vec<Position> {
+    let mut visited: Vec<Vec<bool>> = walkable
+        .iter()
+        .map(|tiles| vec![false; tiles.len()])
+        .collect();
+    let mut largest = Vec::new();
+
+    for (row, tiles) in walkable.iter().enumerate() {
+        for (col, tile) in tiles.iter().enumerate() {
+            if *tile && !visited[row][col] {
+                let region = flood(walkable, Position(col, row), &mut visited);
+                if region.len() > largest.len() {
+                    largest = region;
+                }
+            }
+        }
+    }
+
+    largest
+}
+
+/// Scatters walls over the region without cutting any part of it off.
+fn place_walls(
+    rng: &mut StdRng,
+    region: &[Position],
+    free: &mut Vec<Position>,
+) -> Vec<(Obstacle, Position)> {
+    let (width, height) = region
+        .iter()
+        .fold((0, 0), |(width, height), Position(col, row)| {
+            (width.max(col + 1), height.max(row + 1))
+        });
+    let mut walkable = vec![vec![false; width]; height];
+    for Position(col, row) in region {
+        walkable[*row][*col] = true;
+    }
+
+    let mut obstacles = Vec::new();
+    let mut open = region.len();
+    for _ in 0..region.len() / TILES_PER_WALL {
+        let index = rng.gen_range(0..free.len());
+        let Position(col, row) = free[index];
+
+        walkable[row][col] = false;
+        let Some(start) = free.iter().find(|position| **position != free[index]) else {
+            break;
+        };
+        let mut visited = vec![vec![false; width]; height];
+        if flood(&walkable, *start, &mut visited).len() == open - 1 {
+            obstacles.push((Obstacle::Wall, free.swap_remove(index)));
+            open -= 1;
+        } else {
+            walkable[row][col] = true;
+        }
+    }
+
+    obstacles
+}
+
+/// Draws humans from the registry until the DNA budget runs out.
+fn draw_squad(
+    rng: &mut StdRng,
+    region: &[Position],
+    free: &mut Vec<Position>,
+    registry: &UnitRegistry,
+) -> Vec<(UnitType, Position)> {
+    let (min, max) = TILES_PER_DNA;
+    let mut budget = rng.gen_range(region.len() / min..=region.len() / max) as u16;
+    let mut units = Vec::new();
+
+    loop {
+        let affordable: Vec<_> = registry
+            .0
+            .iter()
+            .filter(|archetype| archetype.dna <= budget)
+            .collect();
+        let Some(archetype) = affordable.choose(rng) else {
+            break;
+        };
+        let Some(position) = free.pop() else {
+            break;
+        };
+
+        budget -= archetype.dna;
+        units.push((UnitType(archetype.name.clone()), position));
+    }
+
+    units.sort_by_key(|(unit_type, _)| {
+        registry
+            .0
+            .iter()
+            .position(|archetype| archetype.name == unit_type.name())
+    });
+    units
+}
+
+/// The least DNA at which a playout found a win for the parasites.
+/// Enough to infect the whole squad at once always wins, so that is the fallback.
+fn tune_initial_dna(rng: &mut StdRng, level: &Level, registry: &UnitRegistry) -> u16 {
+    let range = dna_range(level, registry);
+    let everyone = *range.end();
+
+    least_dna(range, |dna| {
+        let level = Level {
+            initial_dna: dna,
+            ..level.clone()
+        };
+        (0..PLAYOUTS).find(|_| playout(rng, &level, registry) == Some(Winner::Parasites))
+    })
+    .map_or(everyone, |(dna, _)| dna)
+}
+
+/// Plays the level out with parasites that infect and attack whenever they can
+/// and otherwise move at random.
+fn playout(rng: &mut StdRng, level: &Level, registry: &UnitRegistry) -> Option<Winner> {
+    let mut game = GameState::new(level.clone(), registry);
+
+    for _ in 0..PLAYOUT_STEPS {
+        if game.winner().is_some() {
+            break;
+        }
+
+        if game.turn != Turn::Parasites {
+            game.advance();
+            continue;
+        }
+
+        let action = sample_action(rng, &game, 1.0, 0);
+        game.apply(action);
+    }
+
+    game.winner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> UnitRegistry {
+        UnitRegistry::parse(include_bytes!("../assets/units/archetypes.units.ron")).unwrap()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_level() {
+        let registry = registry();
+        for seed in [0, 7, 1234] {
+            let level = generate_level(0, seed, &registry);
+            assert_eq!(level.to_ron(), generate_level(0, seed, &registry).to_ron());
+        }
+    }
+
+    #[test]
+    fn generated_levels_are_valid() {
+        let registry = registry();
+        for seed in [0, 7, 1234] {
+            let level = generate_level(3, seed, &registry);
+            assert_eq!(level.id, 3);
+            assert!(
+                level.validate(&registry).is_ok(),
+                "seed {} is invalid",
+                seed
+            );
+        }
+    }
+}
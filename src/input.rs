@@ -0,0 +1,506 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Position;
+
+/// How far the camera pans per second, in screen pixels, with a key held
+/// or a stick pushed all the way.
+pub const PAN_SPEED: f32 = 640.0;
+
+/// How close to the window's edge the mouse must be to scroll when edge scrolling is on.
+pub const EDGE_SCROLL_MARGIN: f32 = 16.0;
+
+/// How much one wheel notch or zoom key press zooms by.
+pub const ZOOM_STEP: f32 = 1.1;
+
+/// The camera's projection scale limits, from zoomed in to zoomed out.
+pub const MIN_ZOOM: f32 = 0.5;
+pub const MAX_ZOOM: f32 = 2.5;
+
+/// How far a stick must be pushed before it counts.
+pub const STICK_DEADZONE: f32 = 0.5;
+
+/// Something the player wants to do, whichever device it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputAction {
+    /// Select, move or attack at the grid cursor.
+    Confirm,
+    Cancel,
+    NextUnit,
+    PreviousUnit,
+    Infect,
+    EndTurn,
+    Undo,
+    /// Start the level over.
+    Restart,
+    Pause,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    /// Held while moving the mouse to drag the camera.
+    PanCamera,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ZoomIn,
+    ZoomOut,
+    /// Centre the camera on the selected unit.
+    CenterCamera,
+    AttackOverlay,
+    ThreatOverlay,
+    SaveSlot1,
+    SaveSlot2,
+    SaveSlot3,
+    LoadAutosave,
+    LoadSlot1,
+    LoadSlot2,
+    LoadSlot3,
+    /// Switch to the next difficulty and restart the level.
+    CycleDifficulty,
+    /// Turn human stat scaling on or off and restart the level.
+    ToggleStatScaling,
+    ToggleEditor,
+    /// Open the controls screen.
+    Controls,
+    /// Held to paint the editor's brush at the grid cursor.
+    Paint,
+    NextBrush,
+    PreviousBrush,
+    /// Give the edited level's parasites more starting DNA.
+    MoreDna,
+    LessDna,
+    /// Switch the edited level to the next human AI.
+    CycleAi,
+    /// Start a blank level in the editor.
+    NewLevel,
+    /// Write the edited level to its file in the level manifest.
+    SaveLevel,
+}
+
+impl InputAction {
+    pub const ALL: [Self; 42] = [
+        Self::Confirm,
+        Self::Cancel,
+        Self::NextUnit,
+        Self::PreviousUnit,
+        Self::Infect,
+        Self::EndTurn,
+        Self::Undo,
+        Self::Restart,
+        Self::Pause,
+        Self::CursorUp,
+        Self::CursorDown,
+        Self::CursorLeft,
+        Self::CursorRight,
+        Self::PanCamera,
+        Self::PanUp,
+        Self::PanDown,
+        Self::PanLeft,
+        Self::PanRight,
+        Self::ZoomIn,
+        Self::ZoomOut,
+        Self::CenterCamera,
+        Self::AttackOverlay,
+        Self::ThreatOverlay,
+        Self::SaveSlot1,
+        Self::SaveSlot2,
+        Self::SaveSlot3,
+        Self::LoadAutosave,
+        Self::LoadSlot1,
+        Self::LoadSlot2,
+        Self::LoadSlot3,
+        Self::CycleDifficulty,
+        Self::ToggleStatScaling,
+        Self::ToggleEditor,
+        Self::Controls,
+        Self::Paint,
+        Self::NextBrush,
+        Self::PreviousBrush,
+        Self::MoreDna,
+        Self::LessDna,
+        Self::CycleAi,
+        Self::NewLevel,
+        Self::SaveLevel,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Confirm => "Select",
+            Self::Cancel => "Cancel",
+            Self::NextUnit => "Next unit",
+            Self::PreviousUnit => "Previous unit",
+            Self::Infect => "Infect",
+            Self::EndTurn => "End turn",
+            Self::Undo => "Undo",
+            Self::Restart => "Restart",
+            Self::Pause => "Pause",
+            Self::CursorUp => "Cursor up",
+            Self::CursorDown => "Cursor down",
+            Self::CursorLeft => "Cursor left",
+            Self::CursorRight => "Cursor right",
+            Self::PanCamera => "Drag camera",
+            Self::PanUp => "Pan up",
+            Self::PanDown => "Pan down",
+            Self::PanLeft => "Pan left",
+            Self::PanRight => "Pan right",
+            Self::ZoomIn => "Zoom in",
+            Self::ZoomOut => "Zoom out",
+            Self::CenterCamera => "Centre camera",
+            Self::AttackOverlay => "Attack overlay",
+            Self::ThreatOverlay => "Threat overlay",
+            Self::SaveSlot1 => "Save to slot 1",
+            Self::SaveSlot2 => "Save to slot 2",
+            Self::SaveSlot3 => "Save to slot 3",
+            Self::LoadAutosave => "Load autosave",
+            Self::LoadSlot1 => "Load slot 1",
+            Self::LoadSlot2 => "Load slot 2",
+            Self::LoadSlot3 => "Load slot 3",
+            Self::CycleDifficulty => "Difficulty",
+            Self::ToggleStatScaling => "Stat scaling",
+            Self::ToggleEditor => "Level editor",
+            Self::Controls => "Controls",
+            Self::Paint => "Editor paint",
+            Self::NextBrush => "Editor next brush",
+            Self::PreviousBrush => "Editor previous brush",
+            Self::MoreDna => "Editor more DNA",
+            Self::LessDna => "Editor less DNA",
+            Self::CycleAi => "Editor human AI",
+            Self::NewLevel => "Editor new level",
+            Self::SaveLevel => "Editor save level",
+        }
+    }
+
+    /// The save slot each save action writes to.
+    pub fn save_slot(&self) -> Option<usize> {
+        match self {
+            Self::SaveSlot1 => Some(1),
+            Self::SaveSlot2 => Some(2),
+            Self::SaveSlot3 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// The save slot each load action reads from, with the autosave in slot 0.
+    pub fn load_slot(&self) -> Option<usize> {
+        match self {
+            Self::LoadAutosave => Some(0),
+            Self::LoadSlot1 => Some(1),
+            Self::LoadSlot2 => Some(2),
+            Self::LoadSlot3 => Some(3),
+            _ => None,
+        }
+    }
+
+    /// The cell step each cursor action moves the grid cursor by.
+    pub fn cursor_step(&self) -> Option<(isize, isize)> {
+        match self {
+            Self::CursorUp => Some((0, -1)),
+            Self::CursorDown => Some((0, 1)),
+            Self::CursorLeft => Some((-1, 0)),
+            Self::CursorRight => Some((1, 0)),
+            _ => None,
+        }
+    }
+
+    /// The way each pan action moves the camera while held.
+    pub fn pan_direction(&self) -> Option<Vec2> {
+        match self {
+            Self::PanUp => Some(Vec2::Y),
+            Self::PanDown => Some(Vec2::NEG_Y),
+            Self::PanLeft => Some(Vec2::NEG_X),
+            Self::PanRight => Some(Vec2::X),
+            _ => None,
+        }
+    }
+}
+
+/// A key held down along with another to make a different binding, as in Ctrl+Z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Modifier {
+    Ctrl,
+    Alt,
+    Shift,
+}
+
+impl Modifier {
+    pub const ALL: [Self; 3] = [Self::Ctrl, Self::Alt, Self::Shift];
+
+    pub fn keys(&self) -> [KeyCode; 2] {
+        match self {
+            Self::Ctrl => [KeyCode::ControlLeft, KeyCode::ControlRight],
+            Self::Alt => [KeyCode::AltLeft, KeyCode::AltRight],
+            Self::Shift => [KeyCode::ShiftLeft, KeyCode::ShiftRight],
+        }
+    }
+
+    /// The first modifier held down, if any.
+    pub fn held(keyboard_input: &ButtonInput<KeyCode>) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|modifier| keyboard_input.any_pressed(modifier.keys()))
+    }
+
+    pub fn is_modifier(key: KeyCode) -> bool {
+        Self::ALL
+            .iter()
+            .any(|modifier| modifier.keys().contains(&key))
+    }
+}
+
+/// A single key or button, from whichever device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    /// A key pressed with a modifier held.
+    Chord(Modifier, KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+/// Which keys, mouse buttons and gamepad buttons trigger each action.
+/// Missing sections of the settings file fall back to the defaults.
+#[derive(Debug, Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    /// Keys only trigger with no modifier held, so they don't clash with chords.
+    pub keys: Vec<(InputAction, KeyCode)>,
+    pub chords: Vec<(InputAction, Modifier, KeyCode)>,
+    /// Mouse buttons trigger on release, and aim the grid cursor at the mouse first.
+    pub mouse: Vec<(InputAction, MouseButton)>,
+    pub gamepad: Vec<(InputAction, GamepadButtonType)>,
+    /// Whether holding the mouse at the edge of the window scrolls the camera.
+    pub edge_scroll: bool,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use InputAction::*;
+
+        Self {
+            keys: vec![
+                (Confirm, KeyCode::Space),
+                (Cancel, KeyCode::Backspace),
+                (NextUnit, KeyCode::Tab),
+                (NextUnit, KeyCode::KeyE),
+                (PreviousUnit, KeyCode::KeyQ),
+                (Infect, KeyCode::KeyI),
+                (EndTurn, KeyCode::Enter),
+                (Restart, KeyCode::F5),
+                (Pause, KeyCode::Escape),
+                (CursorUp, KeyCode::ArrowUp),
+                (CursorDown, KeyCode::ArrowDown),
+                (CursorLeft, KeyCode::ArrowLeft),
+                (CursorRight, KeyCode::ArrowRight),
+                (PanUp, KeyCode::KeyW),
+                (PanDown, KeyCode::KeyS),
+                (PanLeft, KeyCode::KeyA),
+                (PanRight, KeyCode::KeyD),
+                (ZoomIn, KeyCode::PageUp),
+                (ZoomOut, KeyCode::PageDown),
+                (CenterCamera, KeyCode::KeyC),
+                (AttackOverlay, KeyCode::KeyP),
+                (ThreatOverlay, KeyCode::KeyT),
+                (CycleDifficulty, KeyCode::F3),
+                (ToggleStatScaling, KeyCode::F4),
+                (ToggleEditor, KeyCode::F2),
+                (Controls, KeyCode::F1),
+                (NextBrush, KeyCode::KeyB),
+                (MoreDna, KeyCode::Equal),
+                (LessDna, KeyCode::Minus),
+                (CycleAi, KeyCode::KeyH),
+            ],
+            chords: vec![
+                (Undo, Modifier::Ctrl, KeyCode::KeyZ),
+                (SaveSlot1, Modifier::Ctrl, KeyCode::Digit1),
+                (SaveSlot2, Modifier::Ctrl, KeyCode::Digit2),
+                (SaveSlot3, Modifier::Ctrl, KeyCode::Digit3),
+                (LoadAutosave, Modifier::Alt, KeyCode::Digit0),
+                (LoadSlot1, Modifier::Alt, KeyCode::Digit1),
+                (LoadSlot2, Modifier::Alt, KeyCode::Digit2),
+                (LoadSlot3, Modifier::Alt, KeyCode::Digit3),
+                (PreviousBrush, Modifier::Shift, KeyCode::KeyB),
+                (NewLevel, Modifier::Ctrl, KeyCode::KeyN),
+                (SaveLevel, Modifier::Ctrl, KeyCode::KeyS),
+            ],
+            mouse: vec![
+                (Confirm, MouseButton::Left),
+                (Paint, MouseButton::Left),
+                (PanCamera, MouseButton::Right),
+            ],
+            gamepad: vec![
+                (Confirm, GamepadButtonType::South),
+                (Cancel, GamepadButtonType::East),
+                (NextUnit, GamepadButtonType::RightTrigger),
+                (PreviousUnit, GamepadButtonType::LeftTrigger),
+                (Infect, GamepadButtonType::West),
+                (EndTurn, GamepadButtonType::Start),
+                (Undo, GamepadButtonType::North),
+                (Pause, GamepadButtonType::Select),
+                (CursorUp, GamepadButtonType::DPadUp),
+                (CursorDown, GamepadButtonType::DPadDown),
+                (CursorLeft, GamepadButtonType::DPadLeft),
+                (CursorRight, GamepadButtonType::DPadRight),
+                (ZoomIn, GamepadButtonType::RightTrigger2),
+                (ZoomOut, GamepadButtonType::LeftTrigger2),
+                (CenterCamera, GamepadButtonType::RightThumb),
+            ],
+            edge_scroll: false,
+        }
+    }
+}
+
+impl Bindings {
+    pub fn path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("pestilence").join("bindings.ron"))
+    }
+
+    /// Reads the settings file, or the defaults if there isn't one yet.
+    pub fn load() -> Result<Self, String> {
+        let path = Self::path().ok_or("No user data directory")?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        ron::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("No user data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    /// Everything bound to an action, e.g. "Space, Mouse Left, Pad South".
+    pub fn describe(&self, action: InputAction) -> String {
+        let keys = self
+            .keys
+            .iter()
+            .filter(|(bound, _)| *bound == action)
+            .map(|(_, key)| format!("{:?}", key));
+        let chords = self
+            .chords
+            .iter()
+            .filter(|(bound, _, _)| *bound == action)
+            .map(|(_, modifier, key)| format!("{:?}+{:?}", modifier, key));
+        let mouse = self
+            .mouse
+            .iter()
+            .filter(|(bound, _)| *bound == action)
+            .map(|(_, button)| format!("Mouse {:?}", button));
+        let gamepad = self
+            .gamepad
+            .iter()
+            .filter(|(bound, _)| *bound == action)
+            .map(|(_, button)| format!("Pad {:?}", button));
+
+        let names: Vec<_> = keys.chain(chords).chain(mouse).chain(gamepad).collect();
+        if names.is_empty() {
+            "Unbound".into()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// Binds an action to a key or button in place of whatever it had on that device,
+    /// taking the key or button away from any other action.
+    pub fn rebind(&mut self, action: InputAction, binding: Binding) {
+        match binding {
+            Binding::Key(key) => {
+                self.keys
+                    .retain(|(bound, bound_key)| *bound != action && *bound_key != key);
+                self.chords.retain(|(bound, _, _)| *bound != action);
+                self.keys.push((action, key));
+            }
+            Binding::Chord(modifier, key) => {
+                self.keys.retain(|(bound, _)| *bound != action);
+                self.chords.retain(|(bound, bound_modifier, bound_key)| {
+                    *bound != action && (*bound_modifier, *bound_key) != (modifier, key)
+                });
+                self.chords.push((action, modifier, key));
+            }
+            Binding::Mouse(button) => {
+                self.mouse
+                    .retain(|(bound, bound_button)| *bound != action && *bound_button != button);
+                self.mouse.push((action, button));
+            }
+            Binding::Gamepad(button) => {
+                self.gamepad
+                    .retain(|(bound, bound_button)| *bound != action && *bound_button != button);
+                self.gamepad.push((action, button));
+            }
+        }
+    }
+
+    /// The keys that count with `modifier` held: chords on that modifier, or plain keys
+    /// when there is none, so Ctrl+S doesn't also pan the camera down.
+    pub fn active_keys(
+        &self,
+        modifier: Option<Modifier>,
+    ) -> impl Iterator<Item = (InputAction, KeyCode)> + '_ {
+        let keys = self
+            .keys
+            .iter()
+            .filter(move |(_, key)| modifier.is_none() || Modifier::is_modifier(*key))
+            .copied();
+        let chords = self
+            .chords
+            .iter()
+            .filter(move |(_, chord_modifier, _)| Some(*chord_modifier) == modifier)
+            .map(|(action, _, key)| (*action, *key));
+        keys.chain(chords)
+    }
+
+    pub fn clear(&mut self, action: InputAction) {
+        self.keys.retain(|(bound, _)| *bound != action);
+        self.chords.retain(|(bound, _, _)| *bound != action);
+        self.mouse.retain(|(bound, _)| *bound != action);
+        self.gamepad.retain(|(bound, _)| *bound != action);
+    }
+}
+
+/// What the player asked for this frame. Systems read this instead of the devices,
+/// so the mouse, keyboard and gamepad all behave the same.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct Actions {
+    pub triggered: HashSet<InputAction>,
+    /// Actions whose key or button is held down.
+    pub held: HashSet<InputAction>,
+    /// How far to pan the camera this frame, in screen pixels.
+    pub pan: Vec2,
+    /// Set when the grid cursor was moved by a key or button rather than the mouse,
+    /// so the camera should keep it in view.
+    pub follow_cursor: bool,
+}
+
+impl Actions {
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        self.triggered.contains(&action)
+    }
+
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.held.contains(&action)
+    }
+}
+
+/// The board cell picked out by the mouse, arrow keys or d-pad.
+#[derive(Debug, Clone, Copy, Default, Resource)]
+pub struct GridCursor(pub Option<Position>);
+
+/// The open controls screen, with the action being looked at and whether
+/// the next key or button press will be bound to it.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct Rebinding {
+    pub selected: usize,
+    pub capturing: bool,
+}
@@ -1,12 +1,159 @@
-use crate::{Obstacle, Position, UnitType};
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
 
-#[derive(Debug, Clone)]
+use crate::{HumanAi, Obstacle, Position, UnitRegistry, UnitType, UNIT_REGISTRY};
+
+pub const LEVEL_MANIFEST: &str = "levels/manifest.levels.ron";
+
+/// What a tile in the tilemap is made of. Tiles 1 and 2 are both plain floor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Terrain {
+    Void,
+    Floor,
+    /// Cannot be stood on, but can be shot across.
+    Water,
+    /// Takes two points of speed to cross.
+    Rubble,
+    /// Hurts whoever ends the parasites' turn on it.
+    ToxicSludge,
+    /// Slow to climb, but adds to the range of units attacking from it.
+    HighGround,
+}
+
+impl Terrain {
+    pub const ALL: [Self; 6] = [
+        Self::Void,
+        Self::Floor,
+        Self::Water,
+        Self::Rubble,
+        Self::ToxicSludge,
+        Self::HighGround,
+    ];
+
+    pub fn from_tile(tile: usize) -> Option<Self> {
+        match tile {
+            0 => Some(Self::Void),
+            1 | 2 => Some(Self::Floor),
+            3 => Some(Self::Water),
+            4 => Some(Self::Rubble),
+            5 => Some(Self::ToxicSludge),
+            6 => Some(Self::HighGround),
+            _ => None,
+        }
+    }
+
+    pub fn tile(&self) -> usize {
+        match self {
+            Self::Void => 0,
+            Self::Floor => 1,
+            Self::Water => 3,
+            Self::Rubble => 4,
+            Self::ToxicSludge => 5,
+            Self::HighGround => 6,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Void => "Void",
+            Self::Floor => "Floor",
+            Self::Water => "Water",
+            Self::Rubble => "Rubble",
+            Self::ToxicSludge => "Toxic Sludge",
+            Self::HighGround => "High Ground",
+        }
+    }
+
+    pub fn walkable(&self) -> bool {
+        !matches!(self, Self::Void | Self::Water)
+    }
+
+    pub fn blocks_shots(&self) -> bool {
+        matches!(self, Self::Void)
+    }
+
+    /// Speed spent entering the tile.
+    pub fn movement_cost(&self) -> u16 {
+        match self {
+            Self::Rubble | Self::HighGround => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn damage_per_turn(&self) -> u16 {
+        match self {
+            Self::ToxicSludge => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn range_bonus(&self) -> u16 {
+        match self {
+            Self::HighGround => 1,
+            _ => 0,
+        }
+    }
+
+    /// The largest range bonus any terrain gives.
+    pub fn max_range_bonus() -> u16 {
+        Self::ALL.iter().map(Self::range_bonus).max().unwrap_or(0)
+    }
+
+    /// The tile atlas only has floor art, so other terrain is drawn as tinted floor.
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Void | Self::Floor => Color::WHITE,
+            Self::Water => Color::rgb(0.45, 0.6, 1.0),
+            Self::Rubble => Color::rgb(0.65, 0.55, 0.45),
+            Self::ToxicSludge => Color::rgb(0.55, 1.0, 0.35),
+            Self::HighGround => Color::rgb(1.0, 0.9, 0.7),
+        }
+    }
+}
+
+/// What a strong win of a level looks like, for its star rating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Par {
+    /// Most parasite turns to take.
+    pub turns: usize,
+    /// Least DNA to have left at the end.
+    pub dna: u16,
+    /// Most parasites to lose along the way.
+    pub parasites_lost: usize,
+}
+
+impl Default for Par {
+    fn default() -> Self {
+        Self {
+            turns: 10,
+            dna: 0,
+            parasites_lost: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Level {
+    /// Assigned from the manifest order when loaded.
+    #[serde(default)]
     pub id: usize,
     pub tilemap: Vec<Vec<usize>>,
     pub units: Vec<(UnitType, Position)>,
     pub obstacles: Vec<(Obstacle, Position)>,
     pub initial_dna: u16,
+    #[serde(default)]
+    pub ai: HumanAi,
+    #[serde(default)]
+    pub par: Par,
 }
 
 impl Level {
@@ -20,228 +167,532 @@ impl Level {
         let offset_y = height as f32 / 2.0 * 64.0 - 32.0;
         (offset_x, offset_y)
     }
+
+    pub fn parse(id: usize, bytes: &[u8]) -> Result<Self, ron::error::SpannedError> {
+        let level: Self = ron::de::from_bytes(bytes)?;
+        Ok(Self { id, ..level })
+    }
+
+    /// Formats the level the way the files in `assets/levels` are laid out, one tile row per line.
+    pub fn to_ron(&self) -> String {
+        let mut ron = String::from("(\n    tilemap: [\n");
+        for row in &self.tilemap {
+            let tiles: Vec<_> = row.iter().map(usize::to_string).collect();
+            ron += &format!("        [{}],\n", tiles.join(", "));
+        }
+
+        ron += "    ],\n    units: [\n";
+        for (unit_type, Position(col, row)) in &self.units {
+            ron += &format!("        ({:?}, ({}, {})),\n", unit_type.name(), col, row);
+        }
+
+        ron += "    ],\n    obstacles: [\n";
+        for (obstacle, Position(col, row)) in &self.obstacles {
+            let obstacle = match obstacle {
+                Obstacle::BreakableWall { health, max_health } => format!(
+                    "BreakableWall(health: {}, max_health: {})",
+                    health, max_health
+                ),
+                obstacle => format!("{:?}", obstacle),
+            };
+            ron += &format!("        ({}, ({}, {})),\n", obstacle, col, row);
+        }
+
+        ron += &format!("    ],\n    initial_dna: {},\n", self.initial_dna);
+        if self.ai != HumanAi::default() {
+            ron += &format!("    ai: {:?},\n", self.ai);
+        }
+        if self.par != Par::default() {
+            ron += &format!(
+                "    par: (turns: {}, dna: {}, parasites_lost: {}),\n",
+                self.par.turns, self.par.dna, self.par.parasites_lost
+            );
+        }
+        ron += ")\n";
+        ron
+    }
+
+    /// The terrain at `position`. Anything outside the tilemap counts as the void.
+    pub fn terrain(&self, position: &Position) -> Terrain {
+        let Position(col, row) = *position;
+        self.tilemap
+            .get(row)
+            .and_then(|tiles| tiles.get(col))
+            .and_then(|tile| Terrain::from_tile(*tile))
+            .unwrap_or(Terrain::Void)
+    }
+
+    fn is_walkable(&self, position: &Position) -> Option<bool> {
+        let Position(col, row) = *position;
+        self.tilemap.get(row)?.get(col)?;
+        Some(self.terrain(position).walkable())
+    }
+
+    /// Checks the level for mistakes the rules would otherwise silently misbehave on.
+    pub fn validate(&self, registry: &UnitRegistry) -> Result<(), Vec<LevelError>> {
+        let mut errors = Vec::new();
+
+        let Some(first_row) = self.tilemap.first() else {
+            return Err(vec![LevelError::EmptyTilemap]);
+        };
+        if first_row.is_empty() {
+            return Err(vec![LevelError::EmptyTilemap]);
+        }
+
+        for (row, tiles) in self.tilemap.iter().enumerate() {
+            if tiles.len() != first_row.len() {
+                errors.push(LevelError::RaggedRow {
+                    row,
+                    width: tiles.len(),
+                    expected: first_row.len(),
+                });
+            }
+
+            for (col, tile) in tiles.iter().enumerate() {
+                if Terrain::from_tile(*tile).is_none() {
+                    errors.push(LevelError::UnknownTile {
+                        tile: *tile,
+                        position: Position(col, row),
+                    });
+                }
+            }
+        }
+
+        let mut occupied = HashSet::new();
+        for (unit_type, position) in &self.units {
+            if registry.get(unit_type).is_none() {
+                errors.push(LevelError::UnknownUnitType {
+                    unit_type: unit_type.clone(),
+                    position: *position,
+                });
+            }
+
+            match self.is_walkable(position) {
+                None => errors.push(LevelError::UnitOutOfBounds {
+                    unit_type: unit_type.clone(),
+                    position: *position,
+                }),
+                Some(false) => errors.push(LevelError::UnitOnVoid {
+                    unit_type: unit_type.clone(),
+                    position: *position,
+                }),
+                Some(true) => {}
+            }
+
+            if !occupied.insert(*position) {
+                errors.push(LevelError::OverlappingUnits {
+                    position: *position,
+                });
+            }
+        }
+
+        let mut obstructed = HashSet::new();
+        for (obstacle, position) in &self.obstacles {
+            if self.is_walkable(position).is_none() {
+                errors.push(LevelError::ObstacleOutOfBounds {
+                    obstacle: obstacle.clone(),
+                    position: *position,
+                });
+            }
+
+            if let Obstacle::BreakableWall { health, max_health } = obstacle {
+                if *health == 0 || health > max_health {
+                    errors.push(LevelError::InvalidObstacleHealth {
+                        position: *position,
+                    });
+                }
+            }
+
+            if !obstructed.insert(*position) {
+                errors.push(LevelError::DuplicateObstacle {
+                    position: *position,
+                });
+            } else if occupied.contains(position) {
+                errors.push(LevelError::UnitOnObstacle {
+                    position: *position,
+                });
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LevelError {
+    EmptyTilemap,
+    UnknownUnitType {
+        unit_type: UnitType,
+        position: Position,
+    },
+    RaggedRow {
+        row: usize,
+        width: usize,
+        expected: usize,
+    },
+    UnknownTile {
+        tile: usize,
+        position: Position,
+    },
+    UnitOutOfBounds {
+        unit_type: UnitType,
+        position: Position,
+    },
+    UnitOnVoid {
+        unit_type: UnitType,
+        position: Position,
+    },
+    OverlappingUnits {
+        position: Position,
+    },
+    ObstacleOutOfBounds {
+        obstacle: Obstacle,
+        position: Position,
+    },
+    DuplicateObstacle {
+        position: Position,
+    },
+    InvalidObstacleHealth {
+        position: Position,
+    },
+    UnitOnObstacle {
+        position: Position,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EmptyTilemap => write!(f, "tilemap is empty"),
+            Self::UnknownUnitType {
+                unit_type,
+                position: Position(col, row),
+            } => write!(
+                f,
+                "unit type {} at ({}, {}) is not in the unit registry",
+                unit_type.name(),
+                col,
+                row
+            ),
+            Self::RaggedRow {
+                row,
+                width,
+                expected,
+            } => write!(
+                f,
+                "tilemap row {} has {} tiles but the first row has {}",
+                row, width, expected
+            ),
+            Self::UnknownTile {
+                tile,
+                position: Position(col, row),
+            } => write!(
+                f,
+                "tile {} at ({}, {}) is not a known terrain",
+                tile, col, row
+            ),
+            Self::UnitOutOfBounds {
+                unit_type,
+                position: Position(col, row),
+            } => write!(
+                f,
+                "{} at ({}, {}) is outside the tilemap",
+                unit_type.name(),
+                col,
+                row
+            ),
+            Self::UnitOnVoid {
+                unit_type,
+                position: Position(col, row),
+            } => write!(
+                f,
+                "{} at ({}, {}) is not on a walkable tile",
+                unit_type.name(),
+                col,
+                row
+            ),
+            Self::OverlappingUnits {
+                position: Position(col, row),
+            } => write!(f, "more than one unit at ({}, {})", col, row),
+            Self::ObstacleOutOfBounds {
+                obstacle,
+                position: Position(col, row),
+            } => write!(
+                f,
+                "{} at ({}, {}) is outside the tilemap",
+                obstacle.name(),
+                col,
+                row
+            ),
+            Self::InvalidObstacleHealth {
+                position: Position(col, row),
+            } => write!(
+                f,
+                "breakable wall at ({}, {}) needs health between 1 and its max health",
+                col, row
+            ),
+            Self::DuplicateObstacle {
+                position: Position(col, row),
+            } => write!(f, "more than one obstacle at ({}, {})", col, row),
+            Self::UnitOnObstacle {
+                position: Position(col, row),
+            } => write!(f, "unit and obstacle overlap at ({}, {})", col, row),
+        }
+    }
 }
 
-pub fn levels() -> Vec<Level> {
-    vec![
+/// Formats every problem with a level as one line each, prefixed by where it came from.
+pub fn describe_level_errors(source: &str, errors: &[LevelError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("{}: {}", source, error))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Every level listed in the manifest, with ids following the manifest order.
+#[derive(Debug, Clone, Asset, TypePath, Resource)]
+pub struct Levels(pub Vec<Level>);
+
+impl Levels {
+    /// Reads the manifest and its levels straight from disk, without the asset server.
+    /// Every level is parsed and validated, and all problems found are reported together.
+    pub fn read(manifest: impl AsRef<Path>, registry: &UnitRegistry) -> Result<Self, String> {
+        let manifest = manifest.as_ref();
+        let contents =
+            fs::read(manifest).map_err(|err| format!("{}: {}", manifest.display(), err))?;
+        let files: Vec<String> = ron::de::from_bytes(&contents)
+            .map_err(|err| format!("{}: {}", manifest.display(), err))?;
+
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+        let mut levels = Vec::new();
+        let mut problems = Vec::new();
+        for (id, file) in files.iter().enumerate() {
+            let path = dir.join(file);
+            let source = path.display().to_string();
+            let level = fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|bytes| Level::parse(id, &bytes).map_err(|err| err.to_string()));
+
+            match level {
+                Ok(level) => {
+                    if let Err(errors) = level.validate(registry) {
+                        problems.push(describe_level_errors(&source, &errors));
+                    }
+                    levels.push(level);
+                }
+                Err(err) => problems.push(format!("{}: {}", source, err)),
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(Self(levels))
+        } else {
+            Err(problems.join("\n"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Resource)]
+pub struct LevelsHandle(pub Handle<Levels>);
+
+#[derive(Default)]
+pub struct LevelsLoader;
+
+impl AssetLoader for LevelsLoader {
+    type Asset = Levels;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Levels, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let files: Vec<String> = ron::de::from_bytes(&bytes)?;
+            let registry =
+                UnitRegistry::parse(&load_context.read_asset_bytes(UNIT_REGISTRY).await?)?;
+
+            let dir = load_context
+                .path()
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            let mut levels = Vec::new();
+            for (id, file) in files.iter().enumerate() {
+                let bytes = load_context.read_asset_bytes(dir.join(file)).await?;
+                let level = Level::parse(id, &bytes)?;
+                if let Err(errors) = level.validate(&registry) {
+                    return Err(describe_level_errors(file, &errors).into());
+                }
+                levels.push(level);
+            }
+
+            Ok(Levels(levels))
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["levels.ron"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> UnitRegistry {
+        UnitRegistry::parse(include_bytes!("../assets/units/archetypes.units.ron")).unwrap()
+    }
+
+    /// A 3x2 level with a void tile in the bottom right corner.
+    fn level() -> Level {
         Level {
             id: 0,
-            tilemap: vec![
-                vec![
-                    0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0,
-                ],
-                vec![
-                    0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0,
-                ],
-            ],
-            units: vec![
-                (UnitType::Assault, Position(5, 3)),
-                (UnitType::Assault, Position(5, 5)),
-                (UnitType::Assault, Position(13, 13)),
-                (UnitType::Scout, Position(10, 4)),
-                (UnitType::Scout, Position(17, 18)),
-                (UnitType::Scout, Position(15, 18)),
-                (UnitType::Sniper, Position(15, 3)),
-                (UnitType::Sniper, Position(15, 5)),
-                (UnitType::Ballistic, Position(4, 8)),
-                (UnitType::Ballistic, Position(12, 2)),
-                (UnitType::Juggernaut, Position(13, 7)),
-                (UnitType::Juggernaut, Position(17, 11)),
-                (UnitType::Heavy, Position(16, 10)),
-                (UnitType::Commander, Position(3, 4)),
-                (UnitType::Commander, Position(16, 16)),
-            ],
-            obstacles: vec![
-                (Obstacle::Wall, Position(4, 3)),
-                (Obstacle::Wall, Position(4, 4)),
-                (Obstacle::Wall, Position(4, 5)),
-                (Obstacle::Wall, Position(8, 3)),
-                (Obstacle::Wall, Position(8, 5)),
-                (Obstacle::Wall, Position(12, 3)),
-                (Obstacle::Wall, Position(13, 3)),
-                (Obstacle::Wall, Position(11, 5)),
-                (Obstacle::Wall, Position(14, 6)),
-                (Obstacle::Wall, Position(15, 6)),
-                (Obstacle::Wall, Position(16, 8)),
-                (Obstacle::Wall, Position(15, 10)),
-                (Obstacle::Wall, Position(15, 16)),
-                (Obstacle::Wall, Position(15, 15)),
-                (Obstacle::Wall, Position(16, 15)),
-            ],
-            initial_dna: 4,
-        },
-        Level {
-            id: 1,
-            tilemap: vec![
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
-                ],
-                vec![
-                    0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0,
-                ],
-                vec![
-                    0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-                vec![
-                    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-                ],
-            ],
+            tilemap: vec![vec![1, 1, 1], vec![1, 1, 0]],
             units: vec![
-                (UnitType::Assault, Position(8, 8)),
-                (UnitType::Assault, Position(8, 12)),
-                (UnitType::Assault, Position(12, 8)),
-                (UnitType::Assault, Position(12, 12)),
-                (UnitType::Scout, Position(5, 5)),
-                (UnitType::Scout, Position(5, 15)),
-                (UnitType::Scout, Position(15, 5)),
-                (UnitType::Scout, Position(15, 15)),
-                (UnitType::Sniper, Position(6, 6)),
-                (UnitType::Sniper, Position(6, 14)),
-                (UnitType::Sniper, Position(14, 6)),
-                (UnitType::Sniper, Position(14, 14)),
-                (UnitType::Ballistic, Position(10, 6)),
-                (UnitType::Ballistic, Position(10, 14)),
-                (UnitType::Juggernaut, Position(6, 10)),
-                (UnitType::Juggernaut, Position(14, 10)),
-                (UnitType::Heavy, Position(10, 10)),
-                (UnitType::Commander, Position(10, 2)),
-                (UnitType::Commander, Position(2, 10)),
-                (UnitType::Commander, Position(10, 18)),
-                (UnitType::Commander, Position(18, 10)),
-            ],
-            obstacles: vec![
-                (Obstacle::Wall, Position(8, 10)),
-                (Obstacle::Wall, Position(10, 8)),
-                (Obstacle::Wall, Position(12, 10)),
-                (Obstacle::Wall, Position(10, 12)),
-                (Obstacle::Wall, Position(7, 7)),
-                (Obstacle::Wall, Position(7, 13)),
-                (Obstacle::Wall, Position(13, 7)),
-                (Obstacle::Wall, Position(13, 13)),
-                (Obstacle::Wall, Position(4, 8)),
-                (Obstacle::Wall, Position(16, 8)),
-                (Obstacle::Wall, Position(4, 8)),
-                (Obstacle::Wall, Position(4, 12)),
-                (Obstacle::Wall, Position(8, 4)),
-                (Obstacle::Wall, Position(12, 4)),
-                (Obstacle::Wall, Position(8, 16)),
-                (Obstacle::Wall, Position(12, 16)),
-                (Obstacle::Wall, Position(16, 8)),
-                (Obstacle::Wall, Position(16, 12)),
-                (Obstacle::Wall, Position(3, 10)),
-                (Obstacle::Wall, Position(10, 3)),
-                (Obstacle::Wall, Position(10, 17)),
-                (Obstacle::Wall, Position(17, 10)),
+                (UnitType("Scout".into()), Position(0, 0)),
+                (UnitType("Assault".into()), Position(2, 0)),
             ],
-            initial_dna: 6,
-        },
-    ]
+            obstacles: vec![(Obstacle::Wall, Position(1, 1))],
+            initial_dna: 2,
+            ai: HumanAi::default(),
+            par: Par::default(),
+        }
+    }
+
+    fn errors(level: &Level) -> Vec<LevelError> {
+        level.validate(&registry()).err().unwrap_or_default()
+    }
+
+    #[test]
+    fn shipped_levels_are_valid() {
+        for (id, bytes) in [
+            include_bytes!("../assets/levels/level_0.ron").as_slice(),
+            include_bytes!("../assets/levels/level_1.ron").as_slice(),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let level = Level::parse(id, bytes).unwrap();
+            assert!(
+                level.validate(&registry()).is_ok(),
+                "level {} is invalid",
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn validate_accepts_a_sound_level() {
+        assert!(level().validate(&registry()).is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_an_empty_tilemap() {
+        let mut level = level();
+        level.tilemap = vec![vec![]];
+        assert!(matches!(errors(&level)[..], [LevelError::EmptyTilemap]));
+    }
+
+    #[test]
+    fn validate_rejects_bad_tiles() {
+        let mut level = level();
+        level.tilemap[0].push(1);
+        level.tilemap[1][0] = 9;
+
+        let errors = errors(&level);
+        assert!(matches!(
+            errors[..],
+            [
+                LevelError::RaggedRow {
+                    row: 1,
+                    width: 3,
+                    expected: 4
+                },
+                LevelError::UnknownTile {
+                    tile: 9,
+                    position: Position(0, 1)
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_misplaced_units() {
+        let mut level = level();
+        level.units.extend([
+            (UnitType("Medic".into()), Position(1, 0)),
+            (UnitType("Scout".into()), Position(2, 1)),
+            (UnitType("Scout".into()), Position(5, 5)),
+            (UnitType("Scout".into()), Position(0, 0)),
+        ]);
+
+        let errors = errors(&level);
+        assert!(matches!(
+            errors[..],
+            [
+                LevelError::UnknownUnitType {
+                    position: Position(1, 0),
+                    ..
+                },
+                LevelError::UnitOnVoid {
+                    position: Position(2, 1),
+                    ..
+                },
+                LevelError::UnitOutOfBounds {
+                    position: Position(5, 5),
+                    ..
+                },
+                LevelError::OverlappingUnits {
+                    position: Position(0, 0)
+                },
+            ]
+        ));
+    }
+
+    #[test]
+    fn validate_rejects_misplaced_obstacles() {
+        let mut level = level();
+        level.obstacles.extend([
+            (Obstacle::Boulder, Position(1, 1)),
+            (Obstacle::Boulder, Position(0, 0)),
+            (Obstacle::Wall, Position(3, 0)),
+            (
+                Obstacle::BreakableWall {
+                    health: 0,
+                    max_health: 2,
+                },
+                Position(0, 1),
+            ),
+        ]);
+
+        let errors = errors(&level);
+        assert!(matches!(
+            errors[..],
+            [
+                LevelError::DuplicateObstacle {
+                    position: Position(1, 1)
+                },
+                LevelError::UnitOnObstacle {
+                    position: Position(0, 0)
+                },
+                LevelError::ObstacleOutOfBounds {
+                    position: Position(3, 0),
+                    ..
+                },
+                LevelError::InvalidObstacleHealth {
+                    position: Position(0, 1)
+                },
+            ]
+        ));
+    }
 }
@@ -1,175 +1,58 @@
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
+
 use bevy::{
-    input::mouse::MouseMotion,
+    app::AppExit,
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
     prelude::*,
     text::BreakLineOn,
-    window::{PresentMode, PrimaryWindow},
+    ui::FocusPolicy,
+    window::{PresentMode, PrimaryWindow, WindowResizeConstraints, WindowResized},
 };
-use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
-
+use serde::{Deserialize, Serialize};
+
+mod campaign;
+mod cli;
+mod difficulty;
+mod editor;
+mod game;
+mod generator;
+mod input;
 mod levels;
-
+mod overlay;
+mod planner;
+mod replay;
+mod save;
+mod solver;
+mod units;
+
+pub use campaign::*;
+pub use difficulty::*;
+pub use editor::*;
+pub use game::*;
+pub use generator::*;
+pub use input::*;
 pub use levels::*;
+pub use overlay::*;
+pub use planner::*;
+pub use replay::*;
+pub use save::*;
+pub use solver::*;
+pub use units::*;
 
 pub const GAME_WIDTH: f32 = 1024.0;
 pub const GAME_HEIGHT: f32 = 576.0;
+pub const MIN_WINDOW_WIDTH: f32 = 640.0;
+pub const MIN_WINDOW_HEIGHT: f32 = 360.0;
 pub const BORDER: i16 = 5;
 
-pub const UNITS: [Unit; 7] = [
-    Unit {
-        id: 0,
-        kind: UnitType::Assault,
-        parasite: false,
-        max_health: 5,
-        health: 5,
-        damage: 3,
-        speed: 3,
-        range: 4,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Cardinal,
-            charge: false,
-            aoe: false,
-            all_directions: false,
-        },
-        dna: 2,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Scout,
-        parasite: false,
-        max_health: 3,
-        health: 3,
-        damage: 2,
-        speed: 5,
-        range: 3,
-        move_direction: Direction::Diagonal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Cardinal,
-            charge: false,
-            aoe: false,
-            all_directions: false,
-        },
-        dna: 1,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Sniper,
-        parasite: false,
-        max_health: 4,
-        health: 4,
-        damage: 6,
-        speed: 4,
-        range: 5,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Diagonal,
-            charge: false,
-            aoe: false,
-            all_directions: false,
-        },
-        dna: 2,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Ballistic,
-        parasite: false,
-        max_health: 4,
-        health: 4,
-        damage: 4,
-        speed: 3,
-        range: 2,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Diagonal,
-            charge: false,
-            aoe: true,
-            all_directions: true,
-        },
-        dna: 2,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Juggernaut,
-        parasite: false,
-        max_health: 8,
-        health: 8,
-        damage: 4,
-        speed: 3,
-        range: 4,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Cardinal,
-            charge: true,
-            aoe: false,
-            all_directions: false,
-        },
-        dna: 3,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Heavy,
-        parasite: false,
-        max_health: 14,
-        health: 14,
-        damage: 4,
-        speed: 2,
-        range: 3,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Cardinal,
-            charge: false,
-            aoe: true,
-            all_directions: false,
-        },
-        dna: 3,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-    Unit {
-        id: 0,
-        kind: UnitType::Commander,
-        parasite: false,
-        max_health: 10,
-        health: 10,
-        damage: 5,
-        speed: 3,
-        range: 4,
-        move_direction: Direction::Cardinal,
-        attack_pattern: AttackPattern {
-            direction: Direction::Any,
-            charge: false,
-            aoe: false,
-            all_directions: false,
-        },
-        dna: 4,
-        has_moved: false,
-        has_attacked: false,
-        attack_directions: None,
-    },
-];
-
 #[derive(Event)]
 pub struct ChangeLevel {
     pub level_id: usize,
 }
 
+#[derive(Event)]
+pub struct LoadGame(GameState);
+
 #[derive(Debug, Clone, Resource)]
 pub struct Sprites {
     attack_directions: (Handle<Image>, Handle<TextureAtlasLayout>),
@@ -201,6 +84,15 @@ pub enum Animation {
         timer: Timer,
         entity: Entity,
     },
+    ObstacleMove {
+        start: Position,
+        goal: Position,
+        progress: f32,
+    },
+    ObstacleDestroyed {
+        position: Position,
+        timer: Timer,
+    },
 }
 
 #[derive(Debug, Clone, Resource)]
@@ -210,13 +102,28 @@ pub struct AnimationQueue {
     finished: bool,
 }
 
-#[derive(Debug, Clone, Resource)]
-pub struct CurrentLevel(Level);
+impl AnimationQueue {
+    pub fn push(&mut self, animation: Animation) {
+        if self.queue.is_empty() {
+            self.started = true;
+        }
+        self.queue.push(animation);
+    }
+}
 
 #[derive(Debug, Clone, Resource)]
 pub struct Selected(Option<usize>);
 
-#[derive(Debug, Clone, PartialEq, Resource)]
+/// A generated level to play instead of the first one, chosen with `--seed`
+/// or by playing back a challenge's replay.
+#[derive(Debug, Clone, Resource)]
+pub struct Challenge {
+    seed: u64,
+    /// The generated level's id, once the levels have loaded.
+    level_id: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Turn {
     HumansMove,
     Parasites,
@@ -233,15 +140,6 @@ impl Turn {
     }
 }
 
-#[derive(Debug, Clone, Resource)]
-pub struct TurnOrder(Vec<usize>);
-
-#[derive(Debug, Clone, Resource)]
-pub struct Dna(u16);
-
-#[derive(Debug, Clone, Resource)]
-pub struct Cost(Option<u16>);
-
 #[derive(Debug, Clone, Component)]
 pub struct AnimationTimer {
     timer: Timer,
@@ -252,56 +150,18 @@ pub struct AnimationTimer {
 #[derive(Debug, Clone, Component)]
 pub struct Tile;
 
-#[derive(Debug, Clone)]
-pub enum UnitType {
-    Assault,
-    Scout,
-    Sniper,
-    Ballistic,
-    Juggernaut,
-    Heavy,
-    Commander,
-}
+/// The name of a unit archetype in the `UnitRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UnitType(pub String);
 
 impl UnitType {
-    pub fn index(&self) -> usize {
-        match self {
-            Self::Assault => 0,
-            Self::Scout => 1,
-            Self::Sniper => 2,
-            Self::Ballistic => 3,
-            Self::Juggernaut => 4,
-            Self::Heavy => 5,
-            Self::Commander => 6,
-        }
-    }
-
-    pub fn order(&self) -> u16 {
-        match self {
-            Self::Commander => 0,
-            Self::Scout => 1,
-            Self::Sniper => 2,
-            Self::Assault => 3,
-            Self::Ballistic => 4,
-            Self::Juggernaut => 5,
-            Self::Heavy => 6,
-        }
-    }
-
     pub fn name(&self) -> &str {
-        match self {
-            Self::Assault => "Assault",
-            Self::Scout => "Scout",
-            Self::Sniper => "Sniper",
-            Self::Ballistic => "Ballistic",
-            Self::Juggernaut => "Juggernaut",
-            Self::Heavy => "Heavy",
-            Self::Commander => "Commander",
-        }
+        &self.0
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Direction {
     Cardinal,
     Diagonal,
@@ -334,15 +194,24 @@ impl Direction {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AttackPattern {
     direction: Direction,
     charge: bool,
     aoe: bool,
     all_directions: bool,
+    /// Shoves struck boulders away from the attacker.
+    #[serde(default)]
+    knockback: bool,
 }
 
-#[derive(Debug, Clone, Component)]
+impl AttackPattern {
+    pub fn pushes_boulders(&self) -> bool {
+        self.charge || self.knockback
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Component)]
 pub struct Unit {
     id: usize,
     kind: UnitType,
@@ -355,6 +224,7 @@ pub struct Unit {
     move_direction: Direction,
     attack_pattern: AttackPattern,
     dna: u16,
+    sprite_row: usize,
     has_moved: bool,
     has_attacked: bool,
     attack_directions: Option<Vec<(isize, isize)>>,
@@ -363,31 +233,48 @@ pub struct Unit {
 impl Unit {
     pub fn animation_index(&self) -> usize {
         let offset = if self.parasite { 4 } else { 0 };
-        match self.kind {
-            UnitType::Assault => offset,
-            UnitType::Scout => 8 + offset,
-            UnitType::Sniper => 16 + offset,
-            UnitType::Ballistic => 24 + offset,
-            UnitType::Juggernaut => 32 + offset,
-            UnitType::Heavy => 40 + offset,
-            UnitType::Commander => 48 + offset,
-        }
+        self.sprite_row * 8 + offset
     }
 }
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Component)]
 pub enum Obstacle {
     Wall,
     Boulder,
+    /// A wall that area attacks wear down and eventually knock over.
+    BreakableWall {
+        health: u16,
+        max_health: u16,
+    },
 }
 
 impl Obstacle {
     pub fn index(&self) -> usize {
         match self {
-            Obstacle::Wall => 0,
+            Obstacle::Wall | Obstacle::BreakableWall { .. } => 0,
             Obstacle::Boulder => 1,
         }
     }
+
+    /// The obstacle atlas only has wall art, so breakable walls are drawn as tinted walls
+    /// that darken and redden as they take damage.
+    pub fn color(&self) -> Color {
+        match self {
+            Obstacle::Wall | Obstacle::Boulder => Color::WHITE,
+            Obstacle::BreakableWall { health, max_health } => {
+                let health = *health as f32 / (*max_health).max(1) as f32;
+                Color::rgb(1.0, 0.5 + health * 0.35, 0.35 + health * 0.3)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Obstacle::Wall => "Wall",
+            Obstacle::Boulder => "Boulder",
+            Obstacle::BreakableWall { .. } => "Breakable wall",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Component)]
@@ -407,51 +294,311 @@ pub enum StatText {
     Cost,
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Component)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize, Component)]
 pub struct Position(usize, usize);
 
 #[derive(Debug, Clone, Component)]
 pub struct PossibleMovement;
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, Component)]
 pub struct PossibleAttack(usize, usize);
 
 #[derive(Debug, Clone, Component)]
 pub struct AttackDirection;
 
+/// A clickable button on the side panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum UiButton {
+    Infect,
+    EndTurn,
+    Undo,
+    Restart,
+    Menu,
+}
+
+impl UiButton {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Infect => "Infect",
+            Self::EndTurn => "End Turn",
+            Self::Undo => "Undo",
+            Self::Restart => "Restart",
+            Self::Menu => "Menu",
+        }
+    }
+
+    /// The input action a click stands in for.
+    pub fn action(&self) -> Option<InputAction> {
+        match self {
+            Self::Infect => Some(InputAction::Infect),
+            Self::EndTurn => Some(InputAction::EndTurn),
+            Self::Undo => Some(InputAction::Undo),
+            Self::Restart => Some(InputAction::Restart),
+            Self::Menu => Some(InputAction::Pause),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Infect => Color::GREEN,
+            _ => Color::GRAY,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Component)]
+pub struct EditorText;
+
+#[derive(Debug, Clone, Component)]
+pub struct DifficultyText;
+
+/// The backdrop of the controls screen, shown while `Rebinding` exists.
+#[derive(Debug, Clone, Component)]
+pub struct RebindingScreen;
+
+#[derive(Debug, Clone, Component)]
+pub struct RebindingText;
+
+/// A tile or damage number drawn by the attack prediction overlay.
+#[derive(Debug, Clone, Component)]
+pub struct AttackOverlay;
+
+/// A tile shaded by the threat heatmap.
+#[derive(Debug, Clone, Component)]
+pub struct ThreatOverlay;
+
+/// Which board overlays are switched on.
+#[derive(Debug, Clone, Default, Resource)]
+pub struct Overlays {
+    pub attacks: bool,
+    pub threats: bool,
+}
+
+/// Which screen the game is on. The board is only played on `InGame`; the other
+/// screens are menus drawn over it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, States)]
+pub enum AppState {
+    #[default]
+    MainMenu,
+    LevelSelect,
+    InGame,
+    Paused,
+    Victory,
+    Defeat,
+}
+
+/// The root of a menu screen, despawned when its state is left.
+#[derive(Debug, Clone, Component)]
+pub struct MenuScreen;
+
+/// A button on a menu screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Component)]
+pub enum MenuButton {
+    Play,
+    Controls,
+    Quit,
+    Resume,
+    Restart,
+    NextLevel,
+    LevelSelect,
+    MainMenu,
+    Level(usize),
+}
+
+impl MenuButton {
+    pub fn label(&self) -> String {
+        match self {
+            Self::Play => "Play".into(),
+            Self::Controls => "Controls".into(),
+            Self::Quit => "Quit".into(),
+            Self::Resume => "Resume".into(),
+            Self::Restart => "Restart".into(),
+            Self::NextLevel => "Next Level".into(),
+            Self::LevelSelect => "Level Select".into(),
+            Self::MainMenu => "Main Menu".into(),
+            Self::Level(level_id) => format!("{}", level_id + 1),
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Play | Self::Resume | Self::NextLevel => Color::GREEN,
+            _ => Color::GRAY,
+        }
+    }
+}
+
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Pestilence".into(),
-                name: Some("pestilence".into()),
-                resolution: (GAME_WIDTH, GAME_HEIGHT).into(),
-                present_mode: PresentMode::AutoVsync,
-                enabled_buttons: bevy::window::EnabledButtons {
-                    maximize: false,
-                    ..Default::default()
-                },
-                resizable: false,
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Pestilence".into(),
+            name: Some("pestilence".into()),
+            resolution: (GAME_WIDTH, GAME_HEIGHT).into(),
+            resize_constraints: WindowResizeConstraints {
+                min_width: MIN_WINDOW_WIDTH,
+                min_height: MIN_WINDOW_HEIGHT,
                 ..default()
-            }),
+            },
+            present_mode: PresentMode::AutoVsync,
             ..default()
-        }))
-        .add_event::<ChangeLevel>()
-        .add_systems(Startup, setup)
-        .add_systems(
-            Update,
+        }),
+        ..default()
+    }))
+    .init_asset::<Levels>()
+    .init_asset_loader::<LevelsLoader>()
+    .init_asset::<UnitRegistry>()
+    .init_asset_loader::<UnitRegistryLoader>()
+    .init_resource::<DifficultySettings>()
+    .init_resource::<Overlays>()
+    .init_resource::<Bindings>()
+    .init_resource::<Campaign>()
+    .init_resource::<Actions>()
+    .init_resource::<GridCursor>()
+    .add_event::<ChangeLevel>()
+    .add_event::<LoadGame>()
+    .add_event::<Outcome>()
+    .init_state::<AppState>()
+    .add_systems(Startup, setup)
+    .add_systems(
+        Update,
+        (
+            load_levels.run_if(not(resource_exists::<Levels>)),
+            scale_ui,
             (
-                select_unit,
-                infect_unit,
-                movement,
-                attack,
-                turn,
-                move_camera,
-                (win, listen_change_level).chain(),
-                animate,
-            ),
-        )
-        .run();
+                open_rebinding.run_if(not(resource_exists::<Editor>)),
+                rebind_controls.run_if(resource_exists::<Rebinding>),
+            )
+                .chain()
+                .after(ui_buttons),
+            // The level it starts must be loaded this frame, or `win` would see the old,
+            // already won game once back in `InGame`
+            menu_buttons
+                .run_if(not(in_state(AppState::InGame)))
+                .run_if(not(resource_exists::<Rebinding>))
+                .before(open_rebinding)
+                .before(listen_change_level),
+            (
+                (read_input, ui_buttons)
+                    .chain()
+                    .run_if(in_state(AppState::InGame)),
+                (
+                    select_unit,
+                    infect_unit,
+                    movement,
+                    attack,
+                    undo,
+                    restart,
+                    save_load,
+                    change_difficulty,
+                )
+                    .run_if(not(resource_exists::<Playback>))
+                    .run_if(not(resource_exists::<Editor>))
+                    .run_if(not(resource_exists::<Rebinding>))
+                    .run_if(in_state(AppState::InGame))
+                    .after(ui_buttons)
+                    .before(sync_game),
+                (turn, playback.run_if(resource_exists::<Playback>))
+                    .run_if(not(resource_exists::<Editor>))
+                    .run_if(in_state(AppState::InGame))
+                    .after(ui_buttons)
+                    .before(sync_game),
+                move_camera
+                    .run_if(in_state(AppState::InGame))
+                    .after(read_input),
+                (
+                    sync_game,
+                    win.run_if(not(resource_exists::<Editor>))
+                        .run_if(in_state(AppState::InGame)),
+                )
+                    .chain(),
+                (
+                    toggle_overlays.run_if(in_state(AppState::InGame)),
+                    (show_attack_overlay, show_threat_overlay),
+                )
+                    .chain()
+                    .after(read_input)
+                    .after(sync_game),
+                (
+                    toggle_editor
+                        .run_if(not(resource_exists::<Playback>))
+                        .run_if(not(resource_exists::<Rebinding>)),
+                    edit_level.run_if(resource_exists::<Editor>),
+                )
+                    .chain()
+                    .run_if(in_state(AppState::InGame))
+                    .after(win)
+                    .before(listen_change_level),
+                animate.run_if(in_state(AppState::InGame)),
+                pause
+                    .run_if(not(resource_exists::<Rebinding>))
+                    .run_if(in_state(AppState::InGame))
+                    .after(ui_buttons)
+                    .before(open_rebinding),
+            )
+                .run_if(resource_exists::<GameState>),
+            listen_change_level
+                .run_if(resource_exists::<Levels>)
+                .after(load_levels)
+                .after(win),
+        ),
+    )
+    .add_systems(OnEnter(AppState::MainMenu), spawn_main_menu)
+    .add_systems(OnEnter(AppState::LevelSelect), spawn_level_select)
+    .add_systems(OnEnter(AppState::Paused), spawn_pause_menu)
+    .add_systems(OnEnter(AppState::Victory), spawn_victory_screen)
+    .add_systems(OnEnter(AppState::Defeat), spawn_defeat_screen);
+
+    for state in [
+        AppState::MainMenu,
+        AppState::LevelSelect,
+        AppState::Paused,
+        AppState::Victory,
+        AppState::Defeat,
+    ] {
+        app.add_systems(OnExit(state), despawn_menus);
+    }
+
+    if let Some(seed) = args.iter().skip_while(|arg| *arg != "--seed").nth(1) {
+        match seed.parse() {
+            Ok(seed) => {
+                app.insert_resource(Challenge {
+                    seed,
+                    level_id: None,
+                });
+            }
+            Err(err) => {
+                eprintln!("Invalid seed {}: {}", seed, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = args.iter().skip_while(|arg| *arg != "--replay").nth(1) {
+        match Replay::load(path) {
+            Ok(replay) => {
+                if let Some(seed) = replay.seed {
+                    app.insert_resource(Challenge {
+                        seed,
+                        level_id: None,
+                    });
+                }
+                app.insert_resource(replay.difficulty);
+                app.insert_resource(Playback::from(replay));
+            }
+            Err(err) => {
+                eprintln!("Could not load replay {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    app.run();
 }
 
 fn setup(
@@ -465,7 +612,17 @@ fn setup(
         finished: false,
     });
     commands.insert_resource(Selected(None));
-    commands.insert_resource(Turn::HumansMove);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    match Bindings::load() {
+        Ok(bindings) => commands.insert_resource(bindings),
+        Err(err) => warn!("Could not load bindings: {}", err),
+    }
+
+    match Campaign::load() {
+        Ok(campaign) => commands.insert_resource(campaign),
+        Err(err) => warn!("Could not load campaign: {}", err),
+    }
 
     commands.spawn(Camera2dBundle::default());
 
@@ -479,15 +636,14 @@ fn setup(
                 layout: sprites.selections.1.clone(),
                 index: 0,
             },
-            transform: Transform::from_xyz(-GAME_WIDTH, 0.0, 0.0).with_scale(Vec3::splat(2.0)),
+            transform: Transform::from_scale(Vec3::splat(2.0)),
+            visibility: Visibility::Hidden,
             ..default()
         },
     ));
 
-    let (level, order) = setup_level(&mut commands, &sprites, 0);
-    commands.insert_resource(CurrentLevel(level.clone()));
-    commands.insert_resource(TurnOrder(order));
-    commands.insert_resource(Dna(level.initial_dna));
+    commands.insert_resource(LevelsHandle(asset_server.load(LEVEL_MANIFEST)));
+    commands.insert_resource(UnitRegistryHandle(asset_server.load(UNIT_REGISTRY)));
 
     commands.spawn(AtlasImageBundle {
         style: Style {
@@ -521,7 +677,7 @@ fn setup(
             parent.spawn((
                 StatText::Dna,
                 TextBundle::from_section(
-                    format!("DNA: {}", level.initial_dna),
+                    "",
                     TextStyle {
                         font_size: 12.0,
                         color: Color::WHITE,
@@ -679,51 +835,167 @@ fn setup(
             ));
         });
 
+    commands
+        .spawn((
+            UiButton::Infect,
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(64.0),
+                    height: Val::Px(32.0),
+                    position_type: PositionType::Absolute,
+                    right: Val::Px(64.0),
+                    top: Val::Px(384.0),
+                    border: UiRect::all(Val::Px(2.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                border_color: BorderColor(Color::WHITE),
+                background_color: BackgroundColor(Color::GREEN),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    sections: vec![TextSection {
+                        value: "Infect".into(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..TextStyle::default()
+                        },
+                    }],
+                    justify: JustifyText::Left,
+                    linebreak_behavior: BreakLineOn::NoWrap,
+                },
+                ..default()
+            });
+        });
+
     commands
         .spawn(NodeBundle {
             style: Style {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
-                align_items: AlignItems::Center,
-                justify_content: JustifyContent::Center,
-                left: Val::Px(GAME_WIDTH / 2.0 - 96.0),
-                top: Val::Px(GAME_HEIGHT / 2.0 - 176.0),
+                width: Val::Px(128.0),
+                position_type: PositionType::Absolute,
+                right: Val::Px(32.0),
+                top: Val::Px(456.0),
+                flex_wrap: FlexWrap::Wrap,
+                justify_content: JustifyContent::SpaceBetween,
+                row_gap: Val::Px(8.0),
                 ..default()
             },
             ..default()
         })
         .with_children(|parent| {
-            parent
-                .spawn(ButtonBundle {
-                    style: Style {
-                        width: Val::Px(64.0),
-                        height: Val::Px(32.0),
-                        border: UiRect::all(Val::Px(2.0)),
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::Center,
-                        ..default()
-                    },
-                    border_color: BorderColor(Color::WHITE),
-                    background_color: BackgroundColor(Color::GREEN),
-                    ..default()
-                })
-                .with_children(|parent| {
-                    parent.spawn(TextBundle {
-                        text: Text {
-                            sections: vec![TextSection {
-                                value: "Infect".into(),
-                                style: TextStyle {
-                                    font_size: 16.0,
-                                    color: Color::WHITE,
-                                    ..TextStyle::default()
-                                },
-                            }],
-                            justify: JustifyText::Left,
-                            linebreak_behavior: BreakLineOn::NoWrap,
+            for button in [
+                UiButton::Undo,
+                UiButton::EndTurn,
+                UiButton::Restart,
+                UiButton::Menu,
+            ] {
+                parent
+                    .spawn((
+                        button,
+                        ButtonBundle {
+                            style: Style {
+                                width: Val::Px(60.0),
+                                height: Val::Px(28.0),
+                                border: UiRect::all(Val::Px(2.0)),
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            border_color: BorderColor(Color::WHITE),
+                            background_color: BackgroundColor(button.color()),
+                            ..default()
                         },
-                        ..default()
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle {
+                            text: Text {
+                                sections: vec![TextSection {
+                                    value: button.label().into(),
+                                    style: TextStyle {
+                                        font_size: 12.0,
+                                        color: Color::WHITE,
+                                        ..TextStyle::default()
+                                    },
+                                }],
+                                justify: JustifyText::Left,
+                                linebreak_behavior: BreakLineOn::NoWrap,
+                            },
+                            ..default()
+                        });
                     });
-                });
+            }
+        });
+
+    commands.spawn((
+        DifficultyText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 12.0,
+                color: Color::WHITE,
+                ..TextStyle::default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(16.0),
+            bottom: Val::Px(16.0),
+            ..default()
+        }),
+    ));
+
+    commands.spawn((
+        EditorText,
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 12.0,
+                color: Color::WHITE,
+                ..TextStyle::default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            left: Val::Px(16.0),
+            top: Val::Px(16.0),
+            ..default()
+        }),
+    ));
+
+    commands
+        .spawn((
+            RebindingScreen,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    padding: UiRect::all(Val::Px(32.0)),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+                visibility: Visibility::Hidden,
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                RebindingText,
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font_size: 16.0,
+                        color: Color::WHITE,
+                        ..TextStyle::default()
+                    },
+                ),
+            ));
         });
 }
 
@@ -756,10 +1028,11 @@ fn setup_sprites(
     let ui_background_texture_atlas_layout = texture_atlas_layouts.add(ui_background_layout);
 
     let units_texture = asset_server.load("sprites/units.png");
-    let units_layout = TextureAtlasLayout::from_grid(Vec2::new(32.0, 32.0), 4, 56, None, None);
+    let units_layout =
+        TextureAtlasLayout::from_grid(Vec2::new(32.0, 32.0), 4, UNIT_SPRITE_ROWS * 8, None, None);
     let units_texture_atlas_layout = texture_atlas_layouts.add(units_layout);
 
-    let units_animations = (0..14)
+    let units_animations = (0..UNIT_SPRITE_ROWS * 2)
         .flat_map(|i| {
             [
                 AnimationTimer {
@@ -802,20 +1075,29 @@ fn setup_sprites(
     sprites
 }
 
-fn setup_level(commands: &mut Commands, sprites: &Sprites, level_id: usize) -> (Level, Vec<usize>) {
-    let level = &levels()[level_id];
+fn setup_level(commands: &mut Commands, sprites: &Sprites, game: &GameState) {
+    let level = &game.level;
     let (width, height) = level.dimensions();
     let (offset_x, offset_y) = level.offset();
 
     for (j, row) in level.tilemap.iter().enumerate() {
-        for (i, sprite_index) in row.iter().enumerate() {
+        for (i, tile) in row.iter().enumerate() {
+            let terrain = level.terrain(&Position(i, j));
+            let sprite_index = match terrain {
+                Terrain::Void | Terrain::Floor => *tile,
+                _ => Terrain::Floor.tile(),
+            };
             commands.spawn((
                 Tile,
                 SpriteSheetBundle {
+                    sprite: Sprite {
+                        color: terrain.color(),
+                        ..default()
+                    },
                     texture: sprites.tiles.0.clone(),
                     atlas: TextureAtlas {
                         layout: sprites.tiles.1.clone(),
-                        index: *sprite_index,
+                        index: sprite_index,
                     },
                     transform: Transform::from_xyz(
                         i as f32 * 64.0 - offset_x,
@@ -853,16 +1135,19 @@ fn setup_level(commands: &mut Commands, sprites: &Sprites, level_id: usize) -> (
         }
     }
 
-    for (id, (unit_type, position)) in level.units.iter().enumerate() {
+    spawn_units(commands, sprites, game);
+    spawn_obstacles(commands, sprites, game);
+}
+
+fn spawn_units(commands: &mut Commands, sprites: &Sprites, game: &GameState) {
+    let (offset_x, offset_y) = game.level.offset();
+
+    for (unit, position) in &game.units {
         let Position(col, row) = position;
-        let unit = Unit {
-            id,
-            ..UNITS[unit_type.index()].clone()
-        };
         let timer = sprites.units.2[unit.animation_index()].clone();
         commands.spawn((
             unit.clone(),
-            position.clone(),
+            *position,
             SpriteSheetBundle {
                 texture: sprites.units.0.clone(),
                 atlas: TextureAtlas {
@@ -880,18 +1165,26 @@ fn setup_level(commands: &mut Commands, sprites: &Sprites, level_id: usize) -> (
             timer,
         ));
     }
+}
+
+fn spawn_obstacles(commands: &mut Commands, sprites: &Sprites, game: &GameState) {
+    let (offset_x, offset_y) = game.level.offset();
 
-    for (obstacle, position) in &level.obstacles {
+    for (obstacle, position) in &game.obstacles {
         let Position(col, row) = position;
         commands.spawn((
             obstacle.clone(),
-            position.clone(),
+            *position,
             SpriteSheetBundle {
                 texture: sprites.obstacles.0.clone(),
                 atlas: TextureAtlas {
                     layout: sprites.obstacles.1.clone(),
                     index: obstacle.index(),
                 },
+                sprite: Sprite {
+                    color: obstacle.color(),
+                    ..default()
+                },
                 transform: Transform::from_xyz(
                     *col as f32 * 64.0 - offset_x,
                     offset_y - *row as f32 * 64.0,
@@ -902,50 +1195,273 @@ fn setup_level(commands: &mut Commands, sprites: &Sprites, level_id: usize) -> (
             },
         ));
     }
+}
 
-    let mut turn_order: Vec<_> = level.units.iter().enumerate().collect();
-    turn_order.sort_by(|(_, (unit_a, position_a)), (_, (unit_b, position_b))| {
-        unit_a
-            .order()
-            .cmp(&unit_b.order())
-            .then(position_a.0.cmp(&position_b.0))
-            .then(position_a.1.cmp(&position_b.1))
-    });
-    let turn_order: Vec<_> = turn_order.iter().map(|(id, _)| *id).collect();
+/// The grid cell under the cursor, if it is over the level.
+fn cursor_to_grid(
+    window: &Window,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+    level: &Level,
+) -> Option<Position> {
+    let position = window.cursor_position()?;
+    let world = camera.viewport_to_world_2d(camera_transform, position)?;
+
+    let (width, height) = level.dimensions();
+    let (offset_x, offset_y) = level.offset();
+    let col = ((world.x + offset_x + 32.0) / 64.0).floor();
+    let row = ((offset_y - world.y + 32.0) / 64.0).floor();
 
-    (level.clone(), turn_order)
+    if col >= 0.0 && col < width as f32 && row >= 0.0 && row < height as f32 {
+        Some(Position(col as usize, row as usize))
+    } else {
+        None
+    }
 }
 
-fn listen_change_level(
-    mut commands: Commands,
-    mut events: EventReader<ChangeLevel>,
-    sprites: Res<Sprites>,
-    mut current_level: ResMut<CurrentLevel>,
-    mut turn_order: ResMut<TurnOrder>,
-    mut dna: ResMut<Dna>,
-    tiles: Query<Entity, With<Tile>>,
-    units: Query<Entity, With<Unit>>,
-    obstacles: Query<Entity, With<Obstacle>>,
-    movements: Query<Entity, With<PossibleMovement>>,
-    attacks: Query<Entity, With<PossibleAttack>>,
-    attack_directions: Query<Entity, With<AttackDirection>>,
-) {
-    for event in events.read() {
-        for entity in tiles.iter() {
-            commands
-                .entity(entity)
-                .remove::<(Tile, SpriteSheetBundle)>();
-        }
+/// The next or previous parasite after the selected unit, by turn order.
+fn cycle_parasite(game: &GameState, selected: Option<usize>, forward: bool) -> Option<Position> {
+    let parasites: Vec<_> = game
+        .turn_order
+        .iter()
+        .filter_map(|id| game.unit(*id))
+        .filter(|(unit, _)| unit.parasite)
+        .collect();
+    if parasites.is_empty() {
+        return None;
+    }
 
-        for entity in obstacles.iter() {
-            commands
-                .entity(entity)
-                .remove::<(Obstacle, Position, SpriteSheetBundle)>();
-        }
+    let count = parasites.len();
+    let index = match selected.and_then(|id| parasites.iter().position(|(unit, _)| unit.id == id)) {
+        Some(index) if forward => (index + 1) % count,
+        Some(index) => (index + count - 1) % count,
+        None if forward => 0,
+        None => count - 1,
+    };
+    Some(parasites[index].1)
+}
 
-        for entity in units.iter() {
-            commands
-                .entity(entity)
+// Turns this frame's mouse, keyboard and gamepad input into actions and moves the grid cursor
+fn read_input(
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut cursor_moved: EventReader<CursorMoved>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    time: Res<Time>,
+    bindings: Res<Bindings>,
+    rebinding: Option<Res<Rebinding>>,
+    game: Res<GameState>,
+    selected: Res<Selected>,
+    mut actions: ResMut<Actions>,
+    mut cursor: ResMut<GridCursor>,
+    mut stick_step: Local<Option<(isize, isize)>>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+) {
+    let window = q_windows.single();
+    let camera_transform = camera.single();
+    *actions = Actions::default();
+    if rebinding.is_some() {
+        return;
+    }
+
+    if cursor_moved.read().count() > 0 {
+        cursor.0 = cursor_to_grid(window, camera_transform, &game.level);
+    }
+
+    for (action, button) in &bindings.mouse {
+        if mouse_button_input.pressed(*button) {
+            actions.held.insert(*action);
+        }
+        if mouse_button_input.just_released(*button) && window.cursor_position().is_some() {
+            cursor.0 = cursor_to_grid(window, camera_transform, &game.level);
+            actions.triggered.insert(*action);
+        }
+    }
+
+    for (action, key) in bindings.active_keys(Modifier::held(&keyboard_input)) {
+        if keyboard_input.pressed(key) {
+            actions.held.insert(action);
+        }
+        if keyboard_input.just_pressed(key) {
+            actions.triggered.insert(action);
+        }
+    }
+
+    let mut steps: Vec<(isize, isize)> = Vec::new();
+    for gamepad in gamepads.iter() {
+        for (action, button_type) in &bindings.gamepad {
+            let button = GamepadButton::new(gamepad, *button_type);
+            if gamepad_buttons.pressed(button) {
+                actions.held.insert(*action);
+            }
+            if gamepad_buttons.just_pressed(button) {
+                actions.triggered.insert(*action);
+            }
+        }
+
+        let axis = |axis_type| {
+            gamepad_axes
+                .get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+
+        let pan = Vec2::new(
+            axis(GamepadAxisType::RightStickX),
+            axis(GamepadAxisType::RightStickY),
+        );
+        if pan.length() > STICK_DEADZONE {
+            actions.pan += pan * PAN_SPEED * time.delta_seconds();
+        }
+
+        // The left stick steps the cursor once each time it is pushed past the deadzone
+        let (x, y) = (
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        let step = if x.abs() > STICK_DEADZONE && x.abs() >= y.abs() {
+            Some((x.signum() as isize, 0))
+        } else if y.abs() > STICK_DEADZONE {
+            Some((0, -y.signum() as isize))
+        } else {
+            None
+        };
+        if step != *stick_step {
+            steps.extend(step);
+            *stick_step = step;
+        }
+    }
+
+    let held_pan: Vec2 = actions
+        .held
+        .iter()
+        .filter_map(InputAction::pan_direction)
+        .sum();
+    actions.pan += held_pan.normalize_or_zero() * PAN_SPEED * time.delta_seconds();
+
+    steps.extend(
+        actions
+            .triggered
+            .iter()
+            .filter_map(InputAction::cursor_step),
+    );
+    let (width, height) = game.level.dimensions();
+    for (col_step, row_step) in steps {
+        // Start from the selected unit, or else the first parasite, when the cursor is off the board
+        let Position(col, row) = cursor
+            .0
+            .or_else(|| {
+                selected
+                    .0
+                    .and_then(|id| game.unit(id))
+                    .map(|(_, position)| *position)
+            })
+            .or_else(|| cycle_parasite(&game, None, true))
+            .unwrap_or(Position(width / 2, height / 2));
+
+        cursor.0 = Some(Position(
+            (col as isize + col_step).clamp(0, width as isize - 1) as usize,
+            (row as isize + row_step).clamp(0, height as isize - 1) as usize,
+        ));
+        actions.follow_cursor = true;
+    }
+
+    let next = actions.just_pressed(InputAction::NextUnit);
+    if next || actions.just_pressed(InputAction::PreviousUnit) {
+        if let Some(position) = cycle_parasite(&game, selected.0, next) {
+            cursor.0 = Some(position);
+            actions.follow_cursor = true;
+        }
+    }
+}
+
+fn load_levels(
+    mut commands: Commands,
+    levels_handle: Res<LevelsHandle>,
+    levels_assets: Res<Assets<Levels>>,
+    registry_handle: Res<UnitRegistryHandle>,
+    registry_assets: Res<Assets<UnitRegistry>>,
+    playback: Option<Res<Playback>>,
+    challenge: Option<ResMut<Challenge>>,
+    mut events: EventWriter<ChangeLevel>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if let (Some(levels), Some(registry)) = (
+        levels_assets.get(&levels_handle.0),
+        registry_assets.get(&registry_handle.0),
+    ) {
+        let mut levels = levels.clone();
+        // Challenges and replays skip the menus and go straight to their level
+        let level_id = if let Some(mut challenge) = challenge {
+            levels
+                .0
+                .push(generate_level(levels.0.len(), challenge.seed, registry));
+            challenge.level_id = Some(levels.0.len() - 1);
+            challenge.level_id
+        } else {
+            playback.map(|playback| playback.level_id)
+        };
+
+        match level_id {
+            Some(level_id) if level_id < levels.0.len() => {
+                events.send(ChangeLevel { level_id });
+                next_state.set(AppState::InGame);
+            }
+            Some(level_id) => warn!("The replay is for level {}, which doesn't exist", level_id),
+            None => {}
+        }
+        commands.insert_resource(levels);
+        commands.insert_resource(registry.clone());
+    }
+}
+
+fn listen_change_level(
+    mut commands: Commands,
+    mut change_level_events: EventReader<ChangeLevel>,
+    mut load_game_events: EventReader<LoadGame>,
+    sprites: Res<Sprites>,
+    levels: Res<Levels>,
+    registry: Res<UnitRegistry>,
+    difficulty: Res<DifficultySettings>,
+    mut selected: ResMut<Selected>,
+    tiles: Query<Entity, With<Tile>>,
+    units: Query<Entity, With<Unit>>,
+    obstacles: Query<Entity, With<Obstacle>>,
+    movements: Query<Entity, With<PossibleMovement>>,
+    attacks: Query<Entity, With<PossibleAttack>>,
+    attack_directions: Query<Entity, With<AttackDirection>>,
+) {
+    let games: Vec<_> = change_level_events
+        .read()
+        .filter_map(|event| {
+            let Some(level) = levels.0.get(event.level_id) else {
+                warn!("There is no level {}", event.level_id);
+                return None;
+            };
+            Some(GameState::new(level.clone(), &registry).with_difficulty(*difficulty))
+        })
+        .chain(load_game_events.read().map(|LoadGame(game)| game.clone()))
+        .collect();
+
+    for new_game in games {
+        commands.remove_resource::<PlaybackEnded>();
+
+        for entity in tiles.iter() {
+            commands
+                .entity(entity)
+                .remove::<(Tile, SpriteSheetBundle)>();
+        }
+
+        for entity in obstacles.iter() {
+            commands
+                .entity(entity)
+                .remove::<(Obstacle, Position, SpriteSheetBundle)>();
+        }
+
+        for entity in units.iter() {
+            commands
+                .entity(entity)
                 .remove::<(Unit, Position, SpriteSheetBundle, AnimationTimer)>();
         }
 
@@ -967,28 +1483,21 @@ fn listen_change_level(
                 .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
         }
 
-        let (level, order) = setup_level(&mut commands, &*sprites, event.level_id);
-        current_level.0 = level.clone();
-        turn_order.0 = order;
-        dna.0 = level.initial_dna;
+        setup_level(&mut commands, &sprites, &new_game);
+        commands.insert_resource(new_game);
+        selected.0 = None;
     }
 }
 
 fn select_unit(
     mut commands: Commands,
-    q_windows: Query<&Window, With<PrimaryWindow>>,
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    actions: Res<Actions>,
+    cursor: Res<GridCursor>,
     sprites: Res<Sprites>,
     animation_queue: Res<AnimationQueue>,
-    level: Res<CurrentLevel>,
+    game: Res<GameState>,
     mut selected: ResMut<Selected>,
-    turn: Res<Turn>,
-    mut camera_selection: ParamSet<(
-        Query<&Transform, With<Camera>>,
-        Query<&mut Transform, With<Selection>>,
-    )>,
-    mut units: Query<(&mut Unit, &Position)>,
-    obstacles: Query<(&Obstacle, &Position)>,
+    mut selection: Query<(&mut Transform, &mut Visibility), With<Selection>>,
     mut stat_texts: Query<(&StatText, &mut Text)>,
     movements: Query<Entity, With<PossibleMovement>>,
     attacks: Query<Entity, With<PossibleAttack>>,
@@ -998,58 +1507,47 @@ fn select_unit(
         return;
     }
 
-    if let Some(position) = q_windows.single().cursor_position() {
-        let camera = camera_selection.p0();
-        let camera_transform = camera.iter().next().unwrap();
-        let mouse_x = position.x + camera_transform.translation.x;
-        let mouse_y = position.y - camera_transform.translation.y;
+    let pick = actions.just_pressed(InputAction::Confirm)
+        || actions.just_pressed(InputAction::NextUnit)
+        || actions.just_pressed(InputAction::PreviousUnit);
+    let cancel = actions.just_pressed(InputAction::Cancel);
+    if (pick || cancel) && game.turn == Turn::Parasites {
+        for entity in movements.iter() {
+            commands
+                .entity(entity)
+                .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
+        }
 
-        let CurrentLevel(level) = &*level;
-        let (width, height) = level.dimensions();
-        let (offset_x, offset_y) = level.offset();
-        let col = ((mouse_x - GAME_WIDTH / 2.0 + offset_x + 32.0) / 64.0).floor();
-        let row = ((mouse_y - GAME_HEIGHT / 2.0 + offset_y + 32.0) / 64.0).floor();
+        for entity in attacks.iter() {
+            commands
+                .entity(entity)
+                .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
+        }
 
-        let mut selection = camera_selection.p1();
-        let mut selection_transform = selection.iter_mut().next().unwrap();
-        if col >= 0.0 && col < width as f32 && row >= 0.0 && row < height as f32 {
-            selection_transform.translation.x = col * 64.0 - offset_x;
-            selection_transform.translation.y = offset_y - row * 64.0;
+        for entity in attack_directions.iter() {
+            commands
+                .entity(entity)
+                .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
+        }
 
-            if mouse_button_input.just_released(MouseButton::Left) && *turn == Turn::Parasites {
-                for entity in movements.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
-                }
+        if cancel {
+            selected.0 = None;
+        }
+    }
 
-                for entity in attacks.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
-                }
+    if let Ok((mut selection_transform, mut selection_visibility)) = selection.get_single_mut() {
+        let level = &game.level;
+        let (offset_x, offset_y) = level.offset();
 
-                for entity in attack_directions.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
-                }
+        if let Some(Position(col, row)) = cursor.0 {
+            selection_transform.translation.x = col as f32 * 64.0 - offset_x;
+            selection_transform.translation.y = offset_y - row as f32 * 64.0;
+            *selection_visibility = Visibility::Inherited;
 
-                if let Some((unit, position)) = units
-                    .iter()
-                    .find(|(_, position)| **position == Position(col as usize, row as usize))
-                {
+            if pick && game.turn == Turn::Parasites {
+                if let Some((unit, position)) = game.unit_at(&Position(col, row)) {
                     *selected = Selected(Some(unit.id));
 
-                    let units_list: Vec<_> = units
-                        .iter()
-                        .map(|(unit, position)| (unit.clone(), position.clone()))
-                        .collect();
-                    let obstacles_list: Vec<_> = obstacles
-                        .iter()
-                        .map(|(obstacle, position)| (obstacle.clone(), position.clone()))
-                        .collect();
-
                     let (_, mut text) = stat_texts
                         .iter_mut()
                         .find(|(stat_text, _)| **stat_text == StatText::Name)
@@ -1101,31 +1599,21 @@ fn select_unit(
 
                     if unit.parasite {
                         if !unit.has_attacked {
-                            let (mut unit, position) = units
-                                .iter_mut()
-                                .find(|(_, position)| {
-                                    **position == Position(col as usize, row as usize)
-                                })
-                                .unwrap();
                             if unit.has_moved {
                                 let attacks = possible_attacks(
-                                    &unit,
-                                    &position,
-                                    &level,
-                                    &units_list,
-                                    &obstacles_list,
+                                    unit,
+                                    position,
+                                    level,
+                                    &game.units,
+                                    &game.obstacles,
                                 );
 
-                                if attacks.is_empty() {
-                                    unit.has_attacked = true;
-                                }
-
                                 for (i, attack_direction) in attacks.iter().enumerate() {
                                     for (j, attack) in attack_direction.iter().enumerate() {
                                         let Position(col, row) = attack;
                                         commands.spawn((
                                             PossibleAttack(i, j),
-                                            attack.clone(),
+                                            *attack,
                                             SpriteSheetBundle {
                                                 texture: sprites.selections.0.clone(),
                                                 atlas: TextureAtlas {
@@ -1145,11 +1633,11 @@ fn select_unit(
                                 }
                             } else {
                                 let movements = possible_movements(
-                                    &unit,
+                                    unit,
                                     position,
-                                    &level,
-                                    &units_list,
-                                    &obstacles_list,
+                                    level,
+                                    &game.units,
+                                    &game.obstacles,
                                 );
                                 for movement in movements {
                                     let Position(col, row) = movement;
@@ -1192,12 +1680,12 @@ fn select_unit(
                                 };
 
                                 let positions = attack_positions(
-                                    &unit,
+                                    unit,
                                     *direction,
                                     position,
-                                    &level,
-                                    &units_list,
-                                    &obstacles_list,
+                                    level,
+                                    &game.units,
+                                    &game.obstacles,
                                 );
                                 for position in positions {
                                     let Position(col, row) = position;
@@ -1226,65 +1714,35 @@ fn select_unit(
                 }
             }
         } else {
-            selection_transform.translation.x = -GAME_WIDTH;
+            *selection_visibility = Visibility::Hidden;
         }
-    } else {
-        let mut selection = camera_selection.p1();
-        let mut selection_transform = selection.iter_mut().next().unwrap();
-        selection_transform.translation.x = -GAME_WIDTH;
     }
 }
 
 fn infect_unit(
-    q_windows: Query<&Window, With<PrimaryWindow>>,
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
-    sprites: Res<Sprites>,
+    actions: Res<Actions>,
     animation_queue: Res<AnimationQueue>,
+    mut game: ResMut<GameState>,
     mut selected: ResMut<Selected>,
-    mut dna: ResMut<Dna>,
-    mut units: Query<(&mut Unit, &mut AnimationTimer, &mut TextureAtlas)>,
+    mut outcomes: EventWriter<Outcome>,
     mut stat_texts: Query<(&StatText, &mut Text)>,
 ) {
     if !animation_queue.queue.is_empty() {
         return;
     }
 
-    if let Some(position) = q_windows.single().cursor_position() {
-        // If clicked infect button
-        if mouse_button_input.just_released(MouseButton::Left)
-            && position.x >= GAME_WIDTH - 96.0 - 32.0
-            && position.x <= GAME_WIDTH - 96.0 + 32.0
-            && position.y >= GAME_HEIGHT - 176.0 - 16.0
-            && position.y <= GAME_HEIGHT - 176.0 + 16.0
-        {
-            if let Selected(Some(id)) = *selected {
-                let (mut unit, mut timer, mut unit_texture) =
-                    units.iter_mut().find(|(unit, _, _)| unit.id == id).unwrap();
-
-                let cost = unit.dna * 2;
-                if dna.0 >= cost {
-                    unit.parasite = true;
-
-                    let new_timer = sprites.units.2[unit.animation_index()].clone();
-                    unit_texture.index = new_timer.first;
-                    *timer = new_timer;
-
-                    dna.0 -= cost;
-
-                    let (_, mut text) = stat_texts
-                        .iter_mut()
-                        .find(|(stat_text, _)| **stat_text == StatText::Dna)
-                        .unwrap();
-                    text.sections[0].value = format!("DNA: {}", dna.0);
+    if actions.just_pressed(InputAction::Infect) {
+        if let Selected(Some(id)) = *selected {
+            if let Some(infected) = game.apply(GameAction::Infect { id }) {
+                outcomes.send_batch(infected);
 
-                    let (_, mut text) = stat_texts
-                        .iter_mut()
-                        .find(|(stat_text, _)| **stat_text == StatText::Cost)
-                        .unwrap();
-                    text.sections[0].value = "".into();
+                let (_, mut text) = stat_texts
+                    .iter_mut()
+                    .find(|(stat_text, _)| **stat_text == StatText::Cost)
+                    .unwrap();
+                text.sections[0].value = "".into();
 
-                    selected.0 = None;
-                }
+                selected.0 = None;
             }
         }
     }
@@ -1292,97 +1750,60 @@ fn infect_unit(
 
 fn movement(
     mut commands: Commands,
-    q_windows: Query<&Window, With<PrimaryWindow>>,
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    actions: Res<Actions>,
+    cursor: Res<GridCursor>,
     sprites: Res<Sprites>,
-    mut animation_queue: ResMut<AnimationQueue>,
-    level: Res<CurrentLevel>,
+    animation_queue: Res<AnimationQueue>,
+    mut game: ResMut<GameState>,
     selected: Res<Selected>,
-    camera: Query<&Transform, With<Camera>>,
-    mut units_obstacles_spaces: ParamSet<(
-        Query<(&mut Unit, &mut Position)>,
-        Query<(&Obstacle, &Position)>,
-        Query<(&PossibleMovement, &Position, Entity)>,
-        Query<(&PossibleAttack, &Position, Entity)>,
-    )>,
+    mut outcomes: EventWriter<Outcome>,
+    movements: Query<(&Position, Entity), With<PossibleMovement>>,
+    attacks: Query<Entity, With<PossibleAttack>>,
 ) {
     if !animation_queue.queue.is_empty() {
         return;
     }
 
-    if let Some(position) = q_windows.single().cursor_position() {
-        let camera_transform = camera.iter().next().unwrap();
-        let mouse_x = position.x + camera_transform.translation.x;
-        let mouse_y = position.y - camera_transform.translation.y;
-
-        let CurrentLevel(level) = &*level;
-        let (width, height) = level.dimensions();
-        let (offset_x, offset_y) = level.offset();
-        let col = ((mouse_x - GAME_WIDTH / 2.0 + offset_x + 32.0) / 64.0).floor();
-        let row = ((mouse_y - GAME_HEIGHT / 2.0 + offset_y + 32.0) / 64.0).floor();
-        let movement = Position(col as usize, row as usize);
+    if let Some(movement) = cursor.0 {
+        let (offset_x, offset_y) = game.level.offset();
 
-        let movements = units_obstacles_spaces.p2();
-        if mouse_button_input.just_released(MouseButton::Left)
-            && movements
-                .iter()
-                .any(|(_, position, _)| *position == movement)
+        if actions.just_pressed(InputAction::Confirm)
+            && movements.iter().any(|(position, _)| *position == movement)
         {
             if let Selected(Some(id)) = *selected {
-                for (_, _, entity) in movements.iter() {
+                for (_, entity) in movements.iter() {
                     commands
                         .entity(entity)
                         .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
                 }
 
-                let attacks = units_obstacles_spaces.p3();
-                for (_, _, entity) in attacks.iter() {
+                for entity in attacks.iter() {
                     commands
                         .entity(entity)
                         .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
                 }
 
-                let mut units = units_obstacles_spaces.p0();
-                let (mut unit, mut position) =
-                    units.iter_mut().find(|(unit, _)| unit.id == id).unwrap();
-
-                animation_queue.queue.push(Animation::UnitMove {
+                let Some(moved) = game.apply(GameAction::Move {
                     id,
-                    start: *position,
-                    goal: movement,
-                    progress: 0.0,
-                });
-                animation_queue.started = true;
-
-                *position = movement;
-                unit.has_moved = true;
-
-                let units_list: Vec<_> = units_obstacles_spaces
-                    .p0()
-                    .iter()
-                    .map(|(unit, position)| (unit.clone(), position.clone()))
-                    .collect();
-                let obstacles_list: Vec<_> = units_obstacles_spaces
-                    .p1()
-                    .iter()
-                    .map(|(obstacle, position)| (obstacle.clone(), position.clone()))
-                    .collect();
-                let (unit, position) = units_list.iter().find(|(unit, _)| unit.id == id).unwrap();
-                let attacks =
-                    possible_attacks(unit, &position, &level, &units_list, &obstacles_list);
-
-                if attacks.is_empty() {
-                    let mut units = units_obstacles_spaces.p0();
-                    let (mut unit, _) = units.iter_mut().find(|(unit, _)| unit.id == id).unwrap();
-                    unit.has_attacked = true;
+                    position: movement,
+                }) else {
+                    return;
+                };
+                outcomes.send_batch(moved);
+
+                let (unit, position) = game.unit(id).unwrap();
+                if unit.has_attacked {
+                    return;
                 }
 
+                let attacks =
+                    possible_attacks(unit, position, &game.level, &game.units, &game.obstacles);
                 for (i, attack_direction) in attacks.iter().enumerate() {
                     for (j, attack) in attack_direction.iter().enumerate() {
                         let Position(col, row) = attack;
                         commands.spawn((
                             PossibleAttack(i, j),
-                            attack.clone(),
+                            *attack,
                             SpriteSheetBundle {
                                 texture: sprites.selections.0.clone(),
                                 atlas: TextureAtlas {
@@ -1407,90 +1828,32 @@ fn movement(
 
 fn attack(
     mut commands: Commands,
-    q_windows: Query<&Window, With<PrimaryWindow>>,
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
-    mut animation_queue: ResMut<AnimationQueue>,
-    level: Res<CurrentLevel>,
+    actions: Res<Actions>,
+    cursor: Res<GridCursor>,
+    animation_queue: Res<AnimationQueue>,
+    mut game: ResMut<GameState>,
     selected: Res<Selected>,
-    mut dna: ResMut<Dna>,
-    mut camera_units_attacks: ParamSet<(
-        Query<&Transform, With<Camera>>,
-        Query<(&mut Unit, &mut Position, &mut Transform, Entity)>,
-        Query<(&PossibleAttack, &Position, Entity)>,
-    )>,
+    mut outcomes: EventWriter<Outcome>,
+    attacks: Query<(&PossibleAttack, &Position, Entity)>,
     attack_directions: Query<Entity, With<AttackDirection>>,
-    mut stat_texts: Query<(&StatText, &mut Text)>,
 ) {
     if !animation_queue.queue.is_empty() {
         return;
     }
 
-    if let Some(position) = q_windows.single().cursor_position() {
-        let camera = camera_units_attacks.p0();
-        let camera_transform = camera.iter().next().unwrap();
-        let mouse_x = position.x + camera_transform.translation.x;
-        let mouse_y = position.y - camera_transform.translation.y;
-
-        let CurrentLevel(level) = &*level;
-        let (width, height) = level.dimensions();
-        let (offset_x, offset_y) = level.offset();
-        let col = ((mouse_x - GAME_WIDTH / 2.0 + offset_x + 32.0) / 64.0).floor();
-        let row = ((mouse_y - GAME_HEIGHT / 2.0 + offset_y + 32.0) / 64.0).floor();
-        let attack = Position(col as usize, row as usize);
-
-        let attacks = camera_units_attacks.p2();
-        if mouse_button_input.just_released(MouseButton::Left) {
-            if let Some((attack, position, _)) =
+    if let Some(attack) = cursor.0 {
+        if actions.just_pressed(InputAction::Confirm) {
+            if let Some((target, _, _)) =
                 attacks.iter().find(|(_, position, _)| **position == attack)
             {
-                let attack = attack.clone();
-                let position = position.clone();
                 if let Selected(Some(id)) = *selected {
-                    let mut units = camera_units_attacks.p1();
-                    let (mut unit, mut unit_position, mut transform, _) = units
-                        .iter_mut()
-                        .find(|(unit, _, _, _)| unit.id == id)
-                        .unwrap();
-                    let damage = unit.damage;
-                    let attack_pattern = unit.attack_pattern.clone();
-                    unit.has_attacked = true;
-
-                    if attack_pattern.charge {
-                        let Position(col, row) = position;
-                        let Position(unit_col, unit_row) = *unit_position;
-                        if unit_col < col {
-                            unit_position.0 = col - 1;
-                        } else if unit_col > col {
-                            unit_position.0 = col + 1;
-                        }
-                        if unit_row < row {
-                            unit_position.1 = row - 1;
-                        } else if unit_row > row {
-                            unit_position.1 = row + 1;
-                        }
-
-                        let Position(col, row) = *unit_position;
-                        transform.translation.x = col as f32 * 64.0 - offset_x;
-                        transform.translation.y = offset_y - row as f32 * 64.0;
-                    }
-
-                    let attacks = camera_units_attacks.p2();
-                    let attack_positions = if attack_pattern.aoe {
-                        let PossibleAttack(i, j) = attack;
-                        attacks
-                            .iter()
-                            .filter_map(|(o_attack, position, _)| {
-                                let PossibleAttack(oi, oj) = o_attack;
-                                if *oj <= j && (attack_pattern.all_directions || *oi == i) {
-                                    Some(position.clone())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect()
-                    } else {
-                        vec![position]
+                    let Some(attacked) = game.apply(GameAction::Attack {
+                        id,
+                        target: *target,
+                    }) else {
+                        return;
                     };
+                    outcomes.send_batch(attacked);
 
                     for (_, _, entity) in attacks.iter() {
                         commands
@@ -1503,61 +1866,21 @@ fn attack(
                             .entity(entity)
                             .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
                     }
-
-                    let mut units = camera_units_attacks.p1();
-                    let targets: Vec<_> = units
-                        .iter_mut()
-                        .filter(|(_, position, _, _)| attack_positions.contains(position))
-                        .collect();
-
-                    if animation_queue.queue.is_empty() {
-                        animation_queue.started = true;
-                    }
-                    animation_queue.queue.push(Animation::UnitAttack {
-                        id,
-                        timer: Timer::from_seconds(1.0, TimerMode::Once),
-                    });
-
-                    for (mut target, _, _, entity) in targets {
-                        target.health = target.health.checked_sub(damage).unwrap_or(0);
-
-                        if target.health == 0 {
-                            dna.0 += target.dna;
-
-                            let (_, mut text) = stat_texts
-                                .iter_mut()
-                                .find(|(stat_text, _)| **stat_text == StatText::Dna)
-                                .unwrap();
-                            text.sections[0].value = format!("DNA: {}", dna.0);
-
-                            if animation_queue.queue.is_empty() {
-                                animation_queue.started = true;
-                            }
-                            animation_queue.queue.push(Animation::UnitDeath {
-                                id: target.id,
-                                timer: Timer::from_seconds(0.6, TimerMode::Once),
-                                entity,
-                            });
-                        }
-                    }
                 }
             }
         }
     }
 }
 
-fn turn(
+fn undo(
     mut commands: Commands,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut animation_queue: ResMut<AnimationQueue>,
-    level: Res<CurrentLevel>,
-    mut turn: ResMut<Turn>,
-    turn_order: Res<TurnOrder>,
-    dna: Res<Dna>,
-    mut units_obstacles: ParamSet<(
-        Query<(&mut Unit, &mut Position, &mut Transform, Entity)>,
-        Query<(&Obstacle, &Position)>,
-    )>,
+    actions: Res<Actions>,
+    sprites: Res<Sprites>,
+    animation_queue: Res<AnimationQueue>,
+    mut game: ResMut<GameState>,
+    mut selected: ResMut<Selected>,
+    units: Query<Entity, With<Unit>>,
+    obstacles: Query<Entity, With<Obstacle>>,
     movements: Query<Entity, With<PossibleMovement>>,
     attacks: Query<Entity, With<PossibleAttack>>,
     attack_directions: Query<Entity, With<AttackDirection>>,
@@ -1566,416 +1889,1383 @@ fn turn(
         return;
     }
 
-    match *turn {
-        Turn::Parasites => {
-            let mut units = units_obstacles.p0();
-            let all_units_attacked = units
-                .iter()
-                .all(|(unit, _, _, _)| !unit.parasite || unit.has_attacked);
-            let not_enough_dna = units.iter().all(|(unit, _, _, _)| unit.dna * 2 > dna.0);
-            if keyboard_input.just_released(KeyCode::Enter)
-                || (all_units_attacked && not_enough_dna)
-            {
-                for entity in movements.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
-                }
+    if !actions.just_pressed(InputAction::Undo) || !game.undo() {
+        return;
+    }
 
-                for entity in attacks.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
-                }
+    for entity in movements.iter() {
+        commands
+            .entity(entity)
+            .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
+    }
 
-                for entity in attack_directions.iter() {
-                    commands
-                        .entity(entity)
-                        .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
-                }
+    for entity in attacks.iter() {
+        commands
+            .entity(entity)
+            .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
+    }
 
-                turn.next();
-                units.iter_mut().for_each(|(mut unit, _, _, _)| {
-                    unit.has_moved = false;
-                    unit.has_attacked = false;
-                });
-            }
-        }
-        Turn::HumansMove => {
-            if !units_obstacles
-                .p0()
-                .iter()
-                .any(|(unit, _, _, _)| unit.parasite)
-            {
-                turn.next();
-                return;
-            }
+    for entity in attack_directions.iter() {
+        commands
+            .entity(entity)
+            .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
+    }
 
-            let CurrentLevel(level) = &*level;
-            let TurnOrder(turn_order) = &*turn_order;
+    // Units killed by the undone action have already been removed, so respawn them all
+    for entity in units.iter() {
+        commands
+            .entity(entity)
+            .remove::<(Unit, Position, SpriteSheetBundle, AnimationTimer)>();
+    }
+    spawn_units(&mut commands, &sprites, &game);
 
-            let mut units_list: Vec<_> = units_obstacles
-                .p0()
-                .iter()
-                .map(|(unit, position, _, _)| (unit.clone(), position.clone()))
-                .collect();
-            let obstacles_list: Vec<_> = units_obstacles
-                .p1()
-                .iter()
-                .map(|(obstacle, position)| (obstacle.clone(), position.clone()))
-                .collect();
+    // Boulders may have been pushed
+    for entity in obstacles.iter() {
+        commands
+            .entity(entity)
+            .remove::<(Obstacle, Position, SpriteSheetBundle)>();
+    }
+    spawn_obstacles(&mut commands, &sprites, &game);
 
-            let mut units = units_obstacles.p0();
-            for id in turn_order {
-                if let Some((unit, position, _, _)) =
-                    units.iter().find(|(unit, _, _, _)| unit.id == *id)
-                {
-                    if unit.parasite {
-                        continue;
-                    }
+    selected.0 = None;
+}
 
-                    let attack_positions: Vec<_> = units
-                        .iter()
-                        .flat_map(|(target, position, _, _)| {
-                            if target.parasite {
-                                longest_range_attacks(
-                                    &unit,
-                                    position,
-                                    &level,
-                                    &units_list,
-                                    &obstacles_list,
-                                )
-                            } else {
-                                Vec::new()
-                            }
-                        })
-                        .collect();
-
-                    let mut nearest_attack_position = None;
-                    let mut smallest_cost = u16::MAX;
-                    for direction in attack_positions {
-                        for attack_position in direction {
-                            let path = pathfind(
-                                unit,
-                                position,
-                                &attack_position,
-                                &level,
-                                &units_list,
-                                &obstacles_list,
-                            );
-                            if let Some((path, cost)) = path {
-                                if !path.is_empty() && cost < smallest_cost {
-                                    nearest_attack_position = Some(path[0].clone());
-                                    smallest_cost = cost;
-                                    break;
-                                }
-                            }
-                        }
-                    }
+fn save_load(
+    actions: Res<Actions>,
+    animation_queue: Res<AnimationQueue>,
+    game: Res<GameState>,
+    mut difficulty: ResMut<DifficultySettings>,
+    mut events: EventWriter<LoadGame>,
+) {
+    if !animation_queue.queue.is_empty() {
+        return;
+    }
 
-                    if let Some(attack_position) = nearest_attack_position {
-                        let (_, mut position, _, _) = units
-                            .iter_mut()
-                            .find(|(unit, _, _, _)| unit.id == *id)
-                            .unwrap();
+    let triggered = || {
+        InputAction::ALL
+            .into_iter()
+            .filter(|action| actions.just_pressed(*action))
+    };
 
-                        if animation_queue.queue.is_empty() {
-                            animation_queue.started = true;
-                        }
-                        animation_queue.queue.push(Animation::UnitMove {
-                            id: *id,
-                            start: *position,
-                            goal: attack_position,
-                            progress: 0.0,
-                        });
-                        *position = attack_position.clone();
-
-                        units_list = units_list
-                            .iter()
-                            .map(|(unit, position)| {
-                                if unit.id == *id {
-                                    (unit.clone(), attack_position.clone())
-                                } else {
-                                    (unit.clone(), position.clone())
-                                }
+    // Slot 0 is the autosave, which can be loaded but not saved over
+    if let Some(slot) = triggered().find_map(|action| action.save_slot()) {
+        if game.turn == Turn::Parasites {
+            match SaveFile::save(slot, &game) {
+                Ok(()) => info!("Saved to slot {}", slot),
+                Err(err) => warn!("Could not save to slot {}: {}", slot, err),
+            }
+        }
+    } else if let Some(slot) = triggered().find_map(|action| action.load_slot()) {
+        match SaveFile::load(slot) {
+            Ok(game) => {
+                *difficulty = game.difficulty;
+                events.send(LoadGame(game));
+            }
+            Err(err) => warn!("Could not load slot {}: {}", slot, err),
+        }
+    }
+}
+
+// Restarts from the game's own level rather than its id, since a loaded save's level
+// may not be in this session's level list
+fn restart(
+    actions: Res<Actions>,
+    animation_queue: Res<AnimationQueue>,
+    game: Res<GameState>,
+    registry: Res<UnitRegistry>,
+    difficulty: Res<DifficultySettings>,
+    mut events: EventWriter<LoadGame>,
+) {
+    if actions.just_pressed(InputAction::Restart) && animation_queue.queue.is_empty() {
+        events.send(LoadGame(
+            GameState::new(game.level.clone(), &registry).with_difficulty(*difficulty),
+        ));
+    }
+}
+
+/// The background and border colours of a button.
+fn button_colors(color: Color, enabled: bool, interaction: Interaction) -> (Color, Color) {
+    match (enabled, interaction) {
+        (false, _) => (Color::DARK_GRAY, Color::GRAY),
+        (true, Interaction::Pressed) => (color * 0.6, Color::WHITE),
+        (true, Interaction::Hovered) => (color * 1.3, Color::WHITE),
+        (true, Interaction::None) => (color, Color::WHITE),
+    }
+}
+
+// Shades each button by whether it can be used right now and how the mouse is on it,
+// and turns clicks on usable buttons into input actions
+fn ui_buttons(
+    animation_queue: Res<AnimationQueue>,
+    playback: Option<Res<Playback>>,
+    editor: Option<Res<Editor>>,
+    game: Res<GameState>,
+    selected: Res<Selected>,
+    mut actions: ResMut<Actions>,
+    mut buttons: Query<(
+        &UiButton,
+        Ref<Interaction>,
+        &mut BackgroundColor,
+        &mut BorderColor,
+    )>,
+) {
+    let playing = playback.is_none() && editor.is_none();
+    let ready = playing && animation_queue.queue.is_empty() && game.turn == Turn::Parasites;
+    let mut hovered = false;
+
+    for (button, interaction, mut background_color, mut border_color) in buttons.iter_mut() {
+        let enabled = match button {
+            UiButton::Infect => {
+                ready
+                    && selected
+                        .0
+                        .and_then(|id| game.unit(id))
+                        .is_some_and(|(unit, _)| !unit.parasite && unit.dna * 2 <= game.dna)
+            }
+            UiButton::EndTurn => ready,
+            UiButton::Undo => ready && !game.history.is_empty(),
+            UiButton::Restart => playing,
+            UiButton::Menu => true,
+        };
+
+        (background_color.0, border_color.0) = button_colors(button.color(), enabled, *interaction);
+
+        hovered |= *interaction != Interaction::None;
+        if enabled && interaction.is_changed() && *interaction == Interaction::Pressed {
+            actions.triggered.extend(button.action());
+        }
+    }
+
+    // A click on a button is not also a click on the board under it
+    if hovered {
+        actions.triggered.remove(&InputAction::Confirm);
+    }
+}
+
+/// Cycles the difficulty or toggles stat scaling, restarting the level either way.
+fn change_difficulty(
+    actions: Res<Actions>,
+    bindings: Res<Bindings>,
+    animation_queue: Res<AnimationQueue>,
+    game: Res<GameState>,
+    registry: Res<UnitRegistry>,
+    mut difficulty: ResMut<DifficultySettings>,
+    mut events: EventWriter<LoadGame>,
+    mut difficulty_texts: Query<&mut Text, With<DifficultyText>>,
+) {
+    if animation_queue.queue.is_empty() {
+        let changed = if actions.just_pressed(InputAction::CycleDifficulty) {
+            difficulty.difficulty = difficulty.difficulty.next();
+            true
+        } else if actions.just_pressed(InputAction::ToggleStatScaling) {
+            difficulty.scale_stats = !difficulty.scale_stats;
+            true
+        } else {
+            false
+        };
+
+        if changed {
+            events.send(LoadGame(
+                GameState::new(game.level.clone(), &registry).with_difficulty(*difficulty),
+            ));
+        }
+    }
+
+    if difficulty.is_changed() || bindings.is_changed() {
+        difficulty_texts.single_mut().sections[0].value = format!(
+            "Difficulty: {} ({})\nScaled stats: {} ({})",
+            difficulty.difficulty.name(),
+            bindings.describe(InputAction::CycleDifficulty),
+            if difficulty.scale_stats { "on" } else { "off" },
+            bindings.describe(InputAction::ToggleStatScaling),
+        );
+    }
+}
+
+fn turn(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    animation_queue: Res<AnimationQueue>,
+    playback: Option<Res<Playback>>,
+    mut game: ResMut<GameState>,
+    mut outcomes: EventWriter<Outcome>,
+    movements: Query<Entity, With<PossibleMovement>>,
+    attacks: Query<Entity, With<PossibleAttack>>,
+    attack_directions: Query<Entity, With<AttackDirection>>,
+) {
+    if !animation_queue.queue.is_empty() {
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if game.turn == Turn::HumansMove && playback.is_none() {
+        if let Err(err) = SaveFile::save(AUTOSAVE_SLOT, &game) {
+            warn!("Could not autosave: {}", err);
+        }
+    }
+
+    if game.turn != Turn::Parasites {
+        let advanced = game.advance();
+        outcomes.send_batch(advanced);
+        return;
+    }
+
+    if playback.is_some() {
+        return;
+    }
+
+    if actions.just_pressed(InputAction::EndTurn) || game.is_turn_over() {
+        for entity in movements.iter() {
+            commands
+                .entity(entity)
+                .remove::<(PossibleMovement, Position, SpriteSheetBundle)>();
+        }
+
+        for entity in attacks.iter() {
+            commands
+                .entity(entity)
+                .remove::<(PossibleAttack, Position, SpriteSheetBundle)>();
+        }
+
+        for entity in attack_directions.iter() {
+            commands
+                .entity(entity)
+                .remove::<(AttackDirection, Position, SpriteSheetBundle)>();
+        }
+
+        game.apply(GameAction::EndTurn);
+    }
+}
+
+fn playback(
+    mut commands: Commands,
+    animation_queue: Res<AnimationQueue>,
+    mut game: ResMut<GameState>,
+    mut playback: ResMut<Playback>,
+    mut outcomes: EventWriter<Outcome>,
+) {
+    if !animation_queue.queue.is_empty() || game.turn != Turn::Parasites {
+        return;
+    }
+
+    let Some(action) = playback.actions.pop_front() else {
+        commands.remove_resource::<Playback>();
+        commands.insert_resource(PlaybackEnded);
+        return;
+    };
+
+    match game.apply(action.clone()) {
+        Some(played) => {
+            outcomes.send_batch(played);
+        }
+        None => {
+            warn!("Replay diverged at illegal action {:?}", action);
+            commands.remove_resource::<Playback>();
+            commands.insert_resource(PlaybackEnded);
+        }
+    }
+}
+
+// Mirrors the game state onto the unit entities and queues animations for what happened
+fn sync_game(
+    mut events: EventReader<Outcome>,
+    sprites: Res<Sprites>,
+    game: Res<GameState>,
+    mut animation_queue: ResMut<AnimationQueue>,
+    mut units: Query<(
+        &mut Unit,
+        &mut Position,
+        &mut AnimationTimer,
+        &mut TextureAtlas,
+        Entity,
+    )>,
+    mut obstacles: Query<(&mut Obstacle, &Position, &mut TextureAtlas, &mut Sprite), Without<Unit>>,
+    mut stat_texts: Query<(&StatText, &mut Text)>,
+) {
+    if game.is_changed() {
+        for (mut unit, mut position, _, _, _) in units.iter_mut() {
+            if let Some((game_unit, game_position)) = game.unit(unit.id) {
+                *unit = game_unit.clone();
+                *position = *game_position;
+            }
+        }
+
+        for (mut obstacle, position, mut texture, mut sprite) in obstacles.iter_mut() {
+            if let Some((game_obstacle, _)) = game
+                .obstacles
+                .iter()
+                .find(|(_, game_position)| game_position == position)
+            {
+                *obstacle = game_obstacle.clone();
+                texture.index = obstacle.index();
+                sprite.color = obstacle.color();
+            }
+        }
+
+        let (_, mut text) = stat_texts
+            .iter_mut()
+            .find(|(stat_text, _)| **stat_text == StatText::Dna)
+            .unwrap();
+        text.sections[0].value = format!("DNA: {}", game.dna);
+    }
+
+    for event in events.read() {
+        match *event {
+            Outcome::UnitInfect { id } => {
+                if let Some((unit, _, mut timer, mut texture, _)) =
+                    units.iter_mut().find(|(unit, _, _, _, _)| unit.id == id)
+                {
+                    let new_timer = sprites.units.2[unit.animation_index()].clone();
+                    texture.index = new_timer.first;
+                    *timer = new_timer;
+                }
+            }
+            Outcome::UnitMove { id, start, goal } => {
+                animation_queue.push(Animation::UnitMove {
+                    id,
+                    start,
+                    goal,
+                    progress: 0.0,
+                });
+            }
+            Outcome::UnitAttack { id } => {
+                animation_queue.push(Animation::UnitAttack {
+                    id,
+                    timer: Timer::from_seconds(1.0, TimerMode::Once),
+                });
+            }
+            Outcome::UnitDeath { id } => {
+                if let Some((_, _, _, _, entity)) =
+                    units.iter().find(|(unit, _, _, _, _)| unit.id == id)
+                {
+                    animation_queue.push(Animation::UnitDeath {
+                        id,
+                        timer: Timer::from_seconds(0.6, TimerMode::Once),
+                        entity,
+                    });
+                }
+            }
+            Outcome::ObstacleMove { start, goal } => {
+                animation_queue.push(Animation::ObstacleMove {
+                    start,
+                    goal,
+                    progress: 0.0,
+                });
+            }
+            Outcome::ObstacleDestroyed { position } => {
+                animation_queue.push(Animation::ObstacleDestroyed {
+                    position,
+                    timer: Timer::from_seconds(0.3, TimerMode::Once),
+                });
+            }
+        }
+    }
+}
+
+fn win(
+    animation_queue: Res<AnimationQueue>,
+    game: Res<GameState>,
+    playback: Option<Res<Playback>>,
+    playback_ended: Option<Res<PlaybackEnded>>,
+    challenge: Option<Res<Challenge>>,
+    mut campaign: ResMut<Campaign>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if !animation_queue.queue.is_empty() {
+        return;
+    }
+
+    let Some(winner) = game.winner() else {
+        return;
+    };
+
+    let seed = challenge
+        .filter(|challenge| challenge.level_id == Some(game.level.id))
+        .map(|challenge| challenge.seed);
+    // Games played back from a replay, or taken over from one, would overwrite it
+    let replayed = playback.is_some() || playback_ended.is_some();
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if !replayed {
+        let saved = Replay::path(game.level.id, seed)
+            .ok_or_else(|| "No user data directory".to_string())
+            .and_then(|path| Replay::new(&game, seed).save(path));
+        if let Err(err) = saved {
+            warn!("Could not save replay: {}", err);
+        }
+    }
+
+    // Replays and generated challenges don't count towards the campaign
+    if winner == Winner::Parasites && !replayed && seed.is_none() {
+        campaign.complete(game.level.id, LevelRecord::new(&game));
+        if let Err(err) = campaign.save() {
+            warn!("Could not save campaign: {}", err);
+        }
+    }
+
+    next_state.set(match winner {
+        Winner::Parasites => AppState::Victory,
+        Winner::Humans => AppState::Defeat,
+    });
+}
+
+fn pause(actions: Res<Actions>, mut next_state: ResMut<NextState<AppState>>) {
+    if actions.just_pressed(InputAction::Pause) {
+        next_state.set(AppState::Paused);
+    }
+}
+
+/// Spawns a full-screen menu with a title, then whatever `details` adds, over the given buttons.
+fn spawn_menu(
+    commands: &mut Commands,
+    title: &str,
+    background: Color,
+    details: impl FnOnce(&mut ChildBuilder),
+    buttons: &[MenuButton],
+) {
+    commands
+        .spawn((
+            MenuScreen,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(8.0),
+                    ..default()
+                },
+                background_color: BackgroundColor(background),
+                focus_policy: FocusPolicy::Block,
+                z_index: ZIndex::Global(5),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_section(
+                    title,
+                    TextStyle {
+                        font_size: 32.0,
+                        color: Color::WHITE,
+                        ..TextStyle::default()
+                    },
+                )
+                .with_style(Style {
+                    margin: UiRect::bottom(Val::Px(16.0)),
+                    ..default()
+                }),
+            );
+
+            details(parent);
+
+            for button in buttons {
+                spawn_menu_button(parent, *button, 160.0);
+            }
+        });
+}
+
+fn spawn_menu_button(parent: &mut ChildBuilder, button: MenuButton, width: f32) {
+    parent
+        .spawn((
+            button,
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(width),
+                    height: Val::Px(36.0),
+                    border: UiRect::all(Val::Px(2.0)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                border_color: BorderColor(Color::WHITE),
+                background_color: BackgroundColor(button.color()),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text {
+                    sections: vec![TextSection {
+                        value: button.label(),
+                        style: TextStyle {
+                            font_size: 16.0,
+                            color: Color::WHITE,
+                            ..TextStyle::default()
+                        },
+                    }],
+                    justify: JustifyText::Left,
+                    linebreak_behavior: BreakLineOn::NoWrap,
+                },
+                ..default()
+            });
+        });
+}
+
+fn spawn_main_menu(mut commands: Commands) {
+    let buttons = [
+        MenuButton::Play,
+        MenuButton::Controls,
+        #[cfg(not(target_arch = "wasm32"))]
+        MenuButton::Quit,
+    ];
+    spawn_menu(&mut commands, "Pestilence", Color::BLACK, |_| {}, &buttons);
+}
+
+fn spawn_level_select(
+    mut commands: Commands,
+    levels: Option<Res<Levels>>,
+    campaign: Res<Campaign>,
+) {
+    let level_count = levels.map_or(0, |levels| levels.0.len());
+
+    commands
+        .spawn((
+            MenuScreen,
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(16.0),
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::BLACK),
+                focus_policy: FocusPolicy::Block,
+                z_index: ZIndex::Global(5),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                "Select a level",
+                TextStyle {
+                    font_size: 32.0,
+                    color: Color::WHITE,
+                    ..TextStyle::default()
+                },
+            ));
+
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        max_width: Val::Px(480.0),
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        column_gap: Val::Px(8.0),
+                        row_gap: Val::Px(8.0),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for level_id in 0..level_count {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    flex_direction: FlexDirection::Column,
+                                    align_items: AlignItems::Center,
+                                    row_gap: Val::Px(2.0),
+                                    ..default()
+                                },
+                                ..default()
                             })
-                            .collect();
+                            .with_children(|parent| {
+                                spawn_menu_button(parent, MenuButton::Level(level_id), 64.0);
+
+                                let stars = campaign.best(level_id).map_or(0, |best| best.stars);
+                                parent.spawn(TextBundle {
+                                    text: star_text(stars, 16.0),
+                                    ..default()
+                                });
+                            });
                     }
-                }
+                });
+
+            spawn_menu_button(parent, MenuButton::MainMenu, 160.0);
+        });
+}
+
+fn spawn_pause_menu(mut commands: Commands) {
+    let buttons = [
+        MenuButton::Resume,
+        MenuButton::Restart,
+        MenuButton::Controls,
+        MenuButton::LevelSelect,
+        MenuButton::MainMenu,
+    ];
+    spawn_menu(
+        &mut commands,
+        "Paused",
+        Color::rgba(0.0, 0.0, 0.0, 0.7),
+        |_| {},
+        &buttons,
+    );
+}
+
+/// Gold stars for those earned, grey for the rest.
+fn star_text(stars: u8, font_size: f32) -> Text {
+    let sections: Vec<_> = (1..=3)
+        .map(|star| TextSection {
+            value: if star < 3 { "* ".into() } else { "*".into() },
+            style: TextStyle {
+                font_size,
+                color: if star <= stars {
+                    Color::GOLD
+                } else {
+                    Color::DARK_GRAY
+                },
+                ..TextStyle::default()
+            },
+        })
+        .collect();
+    Text::from_sections(sections)
+}
+
+// Shows the stars earned and how the win measured up to the level's par,
+// with each par value met in green
+fn spawn_victory_screen(mut commands: Commands, game: Res<GameState>) {
+    let record = LevelRecord::new(&game);
+    let par = game.level.par;
+    let [turns_met, dna_met, parasites_met] = record.met(&par);
+    let stats = [
+        (
+            format!("Turns {} (par {})", record.turns, par.turns),
+            turns_met,
+        ),
+        (
+            format!("DNA left {} (par {})", record.dna, par.dna),
+            dna_met,
+        ),
+        (
+            format!(
+                "Parasites lost {} (par {})",
+                record.parasites_lost, par.parasites_lost
+            ),
+            parasites_met,
+        ),
+    ];
+
+    let buttons = [
+        MenuButton::NextLevel,
+        MenuButton::Restart,
+        MenuButton::LevelSelect,
+    ];
+    spawn_menu(
+        &mut commands,
+        "Victory!",
+        Color::rgba(0.0, 0.0, 0.0, 0.7),
+        |parent| {
+            parent.spawn(TextBundle {
+                text: star_text(record.stars, 48.0),
+                ..default()
+            });
+
+            for (stat, met) in stats {
+                parent.spawn(TextBundle::from_section(
+                    stat,
+                    TextStyle {
+                        font_size: 16.0,
+                        color: if met { Color::GREEN } else { Color::WHITE },
+                        ..TextStyle::default()
+                    },
+                ));
             }
 
-            for (mut unit, position, _, _) in units.iter_mut() {
-                if unit.parasite {
-                    continue;
-                }
+            parent.spawn(NodeBundle {
+                style: Style {
+                    height: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            });
+        },
+        &buttons,
+    );
+}
 
-                if unit.attack_pattern.all_directions {
-                    unit.attack_directions = Some(unit.attack_pattern.direction.vectors());
-                    continue;
-                }
+fn spawn_defeat_screen(mut commands: Commands) {
+    let buttons = [MenuButton::Restart, MenuButton::LevelSelect];
+    spawn_menu(
+        &mut commands,
+        "Defeat",
+        Color::rgba(0.0, 0.0, 0.0, 0.7),
+        |_| {},
+        &buttons,
+    );
+}
+
+fn despawn_menus(mut commands: Commands, screens: Query<Entity, With<MenuScreen>>) {
+    for entity in screens.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// Menu buttons can be clicked, or picked with the arrow keys or d-pad and pressed with Enter
+// or the gamepad's South button. Escape or East backs out of the level select and pause menus
+fn menu_buttons(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    levels: Option<Res<Levels>>,
+    registry: Option<Res<UnitRegistry>>,
+    game: Option<Res<GameState>>,
+    difficulty: Res<DifficultySettings>,
+    campaign: Res<Campaign>,
+    state: Res<State<AppState>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut change_level_events: EventWriter<ChangeLevel>,
+    mut load_game_events: EventWriter<LoadGame>,
+    mut app_exit_events: EventWriter<AppExit>,
+    mut focus: Local<Option<usize>>,
+    mut buttons: Query<(
+        &MenuButton,
+        Ref<Interaction>,
+        &mut BackgroundColor,
+        &mut BorderColor,
+    )>,
+) {
+    if state.is_changed() {
+        *focus = None;
+    }
+
+    let gamepad_pressed = |button_type| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
+
+    let count = buttons.iter().len();
+    if count > 0 {
+        if keyboard_input.any_just_pressed([KeyCode::ArrowDown, KeyCode::ArrowRight])
+            || gamepad_pressed(GamepadButtonType::DPadDown)
+            || gamepad_pressed(GamepadButtonType::DPadRight)
+        {
+            *focus = Some(focus.map_or(0, |focus| (focus + 1) % count));
+        } else if keyboard_input.any_just_pressed([KeyCode::ArrowUp, KeyCode::ArrowLeft])
+            || gamepad_pressed(GamepadButtonType::DPadUp)
+            || gamepad_pressed(GamepadButtonType::DPadLeft)
+        {
+            *focus = Some(focus.map_or(count - 1, |focus| (focus + count - 1) % count));
+        }
+    }
+
+    let confirm = keyboard_input.any_just_pressed([KeyCode::Enter, KeyCode::Space])
+        || gamepad_pressed(GamepadButtonType::South);
+    let mut pressed = None;
+    for (i, (button, interaction, mut background_color, mut border_color)) in
+        buttons.iter_mut().enumerate()
+    {
+        let enabled = match button {
+            MenuButton::Play => levels.is_some(),
+            MenuButton::Restart => game.is_some(),
+            MenuButton::NextLevel => game
+                .as_ref()
+                .is_some_and(|game| campaign.is_unlocked(game.level.id + 1)),
+            MenuButton::Level(level_id) => campaign.is_unlocked(*level_id),
+            _ => true,
+        };
+
+        let focused = *focus == Some(i);
+        let shown = if focused && *interaction == Interaction::None {
+            Interaction::Hovered
+        } else {
+            *interaction
+        };
+        (background_color.0, border_color.0) = button_colors(button.color(), enabled, shown);
+
+        let clicked = interaction.is_changed() && *interaction == Interaction::Pressed;
+        if enabled && (clicked || (focused && confirm)) {
+            pressed = Some(*button);
+        }
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Escape) || gamepad_pressed(GamepadButtonType::East) {
+        match state.get() {
+            AppState::LevelSelect => pressed = Some(MenuButton::MainMenu),
+            AppState::Paused => pressed = Some(MenuButton::Resume),
+            _ => {}
+        }
+    }
+
+    let mut play = |level_id| {
+        change_level_events.send(ChangeLevel { level_id });
+        next_state.set(AppState::InGame);
+    };
 
-                let mut directions = Vec::new();
-                'a: for direction in unit.attack_pattern.direction.vectors() {
-                    let mut is_valid = true;
-                    'b: for dist in 1..=unit.range {
-                        let dcol = direction.0 * dist as isize;
-                        let drow = direction.1 * dist as isize;
-                        let Position(col, row) = *position;
+    match pressed {
+        Some(MenuButton::Play | MenuButton::LevelSelect) => next_state.set(AppState::LevelSelect),
+        Some(MenuButton::Controls) => commands.insert_resource(Rebinding::default()),
+        Some(MenuButton::Quit) => {
+            app_exit_events.send(AppExit);
+        }
+        Some(MenuButton::Resume) => next_state.set(AppState::InGame),
+        Some(MenuButton::Restart) => {
+            if let (Some(game), Some(registry)) = (&game, &registry) {
+                load_game_events.send(LoadGame(
+                    GameState::new(game.level.clone(), registry).with_difficulty(*difficulty),
+                ));
+                next_state.set(AppState::InGame);
+            }
+        }
+        Some(MenuButton::NextLevel) => {
+            if let (Some(game), Some(levels)) = (&game, &levels) {
+                play((game.level.id + 1) % levels.0.len());
+            }
+        }
+        Some(MenuButton::MainMenu) => next_state.set(AppState::MainMenu),
+        Some(MenuButton::Level(level_id)) => play(level_id),
+        None => {}
+    }
+}
+
+fn toggle_editor(
+    mut commands: Commands,
+    actions: Res<Actions>,
+    animation_queue: Res<AnimationQueue>,
+    game: Res<GameState>,
+    editor: Option<Res<Editor>>,
+    mut levels: ResMut<Levels>,
+    registry: Res<UnitRegistry>,
+    mut change_level_events: EventWriter<ChangeLevel>,
+    mut load_game_events: EventWriter<LoadGame>,
+    mut editor_texts: Query<&mut Text, With<EditorText>>,
+) {
+    if !actions.just_pressed(InputAction::ToggleEditor) || !animation_queue.queue.is_empty() {
+        return;
+    }
+
+    if let Some(editor) = editor {
+        let mut level = editor.level.clone();
+        if let Err(errors) = level.validate(&registry) {
+            warn!(
+                "Cannot play the edited level:\n{}",
+                describe_level_errors("level", &errors)
+            );
+            return;
+        }
+
+        if let Some(existing) = levels.0.get_mut(level.id) {
+            *existing = level.clone();
+        } else {
+            level.id = levels.0.len();
+            levels.0.push(level.clone());
+        }
+
+        commands.remove_resource::<Editor>();
+        change_level_events.send(ChangeLevel { level_id: level.id });
+        editor_texts.single_mut().sections[0].value = String::new();
+    } else {
+        // Saves and challenges carry their own level, which may share its id with a different
+        // level in the list. It's edited as a new level so saving it doesn't overwrite that one
+        let mut level = game.level.clone();
+        if levels.0.get(level.id) != Some(&level) {
+            level.id = levels.0.len();
+        }
+        let editor = Editor::new(level, &registry);
+        load_game_events.send(LoadGame(GameState::new(editor.level.clone(), &registry)));
+        commands.insert_resource(editor);
+    }
+}
+
+fn edit_level(
+    actions: Res<Actions>,
+    cursor: Res<GridCursor>,
+    levels: Res<Levels>,
+    registry: Res<UnitRegistry>,
+    bindings: Res<Bindings>,
+    mut editor: ResMut<Editor>,
+    mut load_game_events: EventWriter<LoadGame>,
+    mut editor_texts: Query<&mut Text, With<EditorText>>,
+) {
+    let mut changed = false;
+
+    if actions.just_pressed(InputAction::NextBrush) {
+        editor.cycle_brush(true);
+    } else if actions.just_pressed(InputAction::PreviousBrush) {
+        editor.cycle_brush(false);
+    }
+
+    if actions.just_pressed(InputAction::MoreDna) {
+        editor.level.initial_dna += 1;
+        changed = true;
+    } else if actions.just_pressed(InputAction::LessDna) && editor.level.initial_dna > 0 {
+        editor.level.initial_dna -= 1;
+        changed = true;
+    }
+
+    if actions.just_pressed(InputAction::CycleAi) {
+        editor.level.ai = editor.level.ai.next();
+        changed = true;
+    }
+
+    if actions.just_pressed(InputAction::NewLevel) {
+        *editor = Editor::blank(levels.0.len(), &registry);
+        changed = true;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if actions.just_pressed(InputAction::SaveLevel) {
+        let manifest = std::path::Path::new(cli::ASSET_DIR).join(LEVEL_MANIFEST);
+        match editor.save(manifest, &registry) {
+            Ok(path) => info!("Saved level to {}", path.display()),
+            Err(err) => warn!("Could not save level: {}", err),
+        }
+    }
+
+    if actions.pressed(InputAction::Paint) {
+        if let Some(position) = cursor.0 {
+            changed |= editor.paint(position);
+        }
+    }
+
+    if changed {
+        load_game_events.send(LoadGame(GameState::new(editor.level.clone(), &registry)));
+    }
+
+    if editor.is_changed() || bindings.is_changed() {
+        let describe = |action| bindings.describe(action);
+        editor_texts.single_mut().sections[0].value = format!(
+            "Editing level {}\nBrush: {} ({}/{})\nInitial DNA: {} ({}/{})\nHuman AI: {} ({})\n{} save, {} new, {} play",
+            editor.level.id,
+            editor.brush().name(),
+            describe(InputAction::NextBrush),
+            describe(InputAction::PreviousBrush),
+            editor.level.initial_dna,
+            describe(InputAction::MoreDna),
+            describe(InputAction::LessDna),
+            editor.level.ai.name(),
+            describe(InputAction::CycleAi),
+            describe(InputAction::SaveLevel),
+            describe(InputAction::NewLevel),
+            describe(InputAction::ToggleEditor),
+        );
+    }
+}
+
+fn toggle_overlays(actions: Res<Actions>, mut overlays: ResMut<Overlays>) {
+    if actions.just_pressed(InputAction::AttackOverlay) {
+        overlays.attacks = !overlays.attacks;
+    }
+    if actions.just_pressed(InputAction::ThreatOverlay) {
+        overlays.threats = !overlays.threats;
+    }
+}
+
+/// The controls action opens the controls screen, which is shown for as long as `Rebinding` exists.
+/// It reads the keys itself, since `Actions` is only filled in during a game.
+fn open_rebinding(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<Bindings>,
+    rebinding: Option<Res<Rebinding>>,
+    mut screens: Query<&mut Visibility, With<RebindingScreen>>,
+) {
+    *screens.single_mut() = if rebinding.is_some() {
+        Visibility::Inherited
+    } else {
+        Visibility::Hidden
+    };
+
+    let controls_pressed = bindings
+        .active_keys(Modifier::held(&keyboard_input))
+        .any(|(action, key)| action == InputAction::Controls && keyboard_input.just_pressed(key));
+    if rebinding.is_none() && controls_pressed {
+        commands.insert_resource(Rebinding::default());
+    }
+}
+
+// Up and down pick an action, Enter binds the next key or button pressed to it,
+// Delete unbinds it, R puts every binding back to the defaults and E toggles edge scrolling.
+// Escape or the controls key saves the bindings and closes the screen.
+// Holding Ctrl, Alt or Shift while pressing a key binds the combination
+fn rebind_controls(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut bindings: ResMut<Bindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut rebinding_texts: Query<&mut Text, With<RebindingText>>,
+) {
+    let action = InputAction::ALL[rebinding.selected];
+    let gamepad_pressed = |button_type| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
 
-                        if -dcol > col as isize || -drow > row as isize {
-                            break 'b;
-                        }
+    // The press that opened the screen is not meant for it
+    if !rebinding.is_added() {
+        if rebinding.capturing {
+            let pressed_gamepad = gamepad_buttons
+                .get_just_pressed()
+                .find(|button| gamepads.contains(button.gamepad))
+                .map(|button| Binding::Gamepad(button.button_type));
+            let modifier = Modifier::held(&keyboard_input);
+            let pressed = keyboard_input
+                .get_just_pressed()
+                .find(|key| !Modifier::is_modifier(**key))
+                .map(|key| match modifier {
+                    Some(modifier) if *key != KeyCode::Escape => Binding::Chord(modifier, *key),
+                    _ => Binding::Key(*key),
+                })
+                .or_else(|| {
+                    mouse_button_input
+                        .get_just_pressed()
+                        .next()
+                        .map(|button| Binding::Mouse(*button))
+                })
+                .or(pressed_gamepad);
 
-                        let attack = Position(
-                            (col as isize + dcol) as usize,
-                            (row as isize + drow) as usize,
-                        );
-
-                        if obstacles_list
-                            .iter()
-                            .any(|(_, position)| *position == attack)
-                        {
-                            if dist == 1 {
-                                is_valid = false;
-                            }
-                            break 'b;
-                        } else if let Some((target, _)) =
-                            units_list.iter().find(|(_, position)| *position == attack)
-                        {
-                            if target.parasite {
-                                unit.attack_directions = Some(vec![direction]);
-                                break 'a;
-                            } else {
-                                is_valid = false;
-                                break 'b;
-                            }
-                        }
-                    }
-                    if is_valid {
-                        directions.push(direction);
-                    }
+            match pressed {
+                Some(Binding::Key(KeyCode::Escape)) => rebinding.capturing = false,
+                Some(binding) => {
+                    bindings.rebind(action, binding);
+                    rebinding.capturing = false;
                 }
-
-                if unit.attack_directions.is_none() && !directions.is_empty() {
-                    directions.sort_by(|(dcol_a, drow_a), (dcol_b, drow_b)| {
-                        let mut a_score = 0;
-                        let mut b_score = 0;
-                        for (target, target_position) in &units_list {
-                            let Position(col, row) = *position;
-                            let Position(target_col, target_row) = target_position;
-                            if target.parasite {
-                                if (*target_col < col && *dcol_a == -1)
-                                    || (*target_col > col && *dcol_a == 1)
-                                    || (*target_col == col && *dcol_a == 0)
-                                {
-                                    a_score += 1;
-                                }
-                                if (*target_row < row && *drow_a == -1)
-                                    || (*target_row > row && *drow_a == 1)
-                                    || (*target_row == row && *drow_a == 0)
-                                {
-                                    a_score += 1;
-                                }
-
-                                if (*target_col < col && *dcol_b == -1)
-                                    || (*target_col > col && *dcol_b == 1)
-                                    || (*target_col == col && *dcol_b == 0)
-                                {
-                                    b_score += 1;
-                                }
-                                if (*target_row < row && *drow_b == -1)
-                                    || (*target_row > row && *drow_b == 1)
-                                    || (*target_row == row && *drow_b == 0)
-                                {
-                                    b_score += 1;
-                                }
-                            }
-                        }
-                        a_score.cmp(&b_score).reverse()
+                None => {}
+            }
+        } else {
+            let count = InputAction::ALL.len();
+            let controls_pressed =
+                bindings
+                    .active_keys(Modifier::held(&keyboard_input))
+                    .any(|(action, key)| {
+                        action == InputAction::Controls && keyboard_input.just_pressed(key)
                     });
-                    unit.attack_directions = Some(vec![directions[0]]);
+            if keyboard_input.just_pressed(KeyCode::Escape)
+                || controls_pressed
+                || gamepad_pressed(GamepadButtonType::East)
+            {
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Err(err) = bindings.save() {
+                    warn!("Could not save bindings: {}", err);
                 }
+
+                commands.remove_resource::<Rebinding>();
+            } else if keyboard_input.just_pressed(KeyCode::ArrowDown)
+                || gamepad_pressed(GamepadButtonType::DPadDown)
+            {
+                rebinding.selected = (rebinding.selected + 1) % count;
+            } else if keyboard_input.just_pressed(KeyCode::ArrowUp)
+                || gamepad_pressed(GamepadButtonType::DPadUp)
+            {
+                rebinding.selected = (rebinding.selected + count - 1) % count;
+            } else if keyboard_input.just_pressed(KeyCode::Enter)
+                || gamepad_pressed(GamepadButtonType::South)
+            {
+                rebinding.capturing = true;
+            } else if keyboard_input.just_pressed(KeyCode::Delete)
+                || gamepad_pressed(GamepadButtonType::West)
+            {
+                bindings.clear(action);
+            } else if keyboard_input.just_pressed(KeyCode::KeyR)
+                || gamepad_pressed(GamepadButtonType::North)
+            {
+                *bindings = Bindings::default();
+            } else if keyboard_input.just_pressed(KeyCode::KeyE) {
+                bindings.edge_scroll = !bindings.edge_scroll;
             }
+        }
+    }
+
+    if rebinding.is_changed() || bindings.is_changed() {
+        let mut value = if rebinding.capturing {
+            format!(
+                "Press a key or button for {} (Escape cancels)\n\n",
+                InputAction::ALL[rebinding.selected].name()
+            )
+        } else {
+            format!(
+                "Controls\nUp/Down choose, Enter rebind, Delete unbind, R reset, Escape close\nEdge scrolling: {} (E)\n\n",
+                if bindings.edge_scroll { "on" } else { "off" },
+            )
+        };
 
-            turn.next();
+        // Only part of the list fits on screen, so it scrolls to keep the selection in view
+        const ROWS: usize = 20;
+        let first = rebinding
+            .selected
+            .saturating_sub(ROWS / 2)
+            .min(InputAction::ALL.len() - ROWS);
+        for (i, action) in InputAction::ALL.iter().enumerate().skip(first).take(ROWS) {
+            let marker = if i == rebinding.selected { ">" } else { " " };
+            value += &format!(
+                "{} {}: {}\n",
+                marker,
+                action.name(),
+                bindings.describe(*action)
+            );
         }
-        Turn::HumansAttack => {
-            let CurrentLevel(level) = &*level;
-            let TurnOrder(turn_order) = &*turn_order;
-            let (width, height) = level.dimensions();
-            let (offset_x, offset_y) = level.offset();
 
-            for id in turn_order {
-                if let Some((unit, position, _, _)) = units_obstacles
-                    .p0()
-                    .iter()
-                    .find(|(unit, _, _, _)| unit.id == *id)
-                {
-                    let unit = unit.clone();
-                    let position = position.clone();
-                    if unit.parasite {
-                        continue;
-                    }
+        rebinding_texts.single_mut().sections[0].value = value;
+    }
+}
 
-                    if let Some(attack_directions) = unit.attack_directions {
-                        if animation_queue.queue.is_empty() {
-                            animation_queue.started = true;
-                        }
-                        animation_queue.queue.push(Animation::UnitAttack {
-                            id: *id,
-                            timer: Timer::from_seconds(1.0, TimerMode::Once),
-                        });
+/// Redraws the tiles the humans' telegraphed attacks will hit, with the damage each unit
+/// will take and who will die, whenever the board changes.
+fn show_attack_overlay(
+    mut commands: Commands,
+    sprites: Res<Sprites>,
+    overlays: Res<Overlays>,
+    game: Res<GameState>,
+    attack_overlays: Query<Entity, With<AttackOverlay>>,
+) {
+    if !overlays.is_changed() && !game.is_changed() {
+        return;
+    }
 
-                        for direction in attack_directions {
-                            for dist in 1..=unit.range {
-                                let dcol = direction.0 * dist as isize;
-                                let drow = direction.1 * dist as isize;
-                                let Position(col, row) = position;
+    for entity in attack_overlays.iter() {
+        commands
+            .entity(entity)
+            .remove::<(AttackOverlay, SpriteSheetBundle, Text2dBundle)>();
+    }
 
-                                if -dcol > col as isize || -drow > row as isize {
-                                    break;
-                                }
+    if !overlays.attacks {
+        return;
+    }
 
-                                let attack = Position(
-                                    (col as isize + dcol) as usize,
-                                    (row as isize + drow) as usize,
-                                );
-                                let Position(col, row) = attack;
-
-                                if col >= width
-                                    || row >= height
-                                    || level.tilemap[row][col] == 0
-                                    || units_obstacles
-                                        .p1()
-                                        .iter()
-                                        .any(|(_, position)| *position == attack)
-                                {
-                                    break;
-                                } else if let Some((mut target, target_position, _, entity)) =
-                                    units_obstacles
-                                        .p0()
-                                        .iter_mut()
-                                        .find(|(_, position, _, _)| **position == attack)
-                                {
-                                    target.health =
-                                        target.health.checked_sub(unit.damage).unwrap_or(0);
-
-                                    if target.health == 0 {
-                                        if animation_queue.queue.is_empty() {
-                                            animation_queue.started = true;
-                                        }
-                                        animation_queue.queue.push(Animation::UnitDeath {
-                                            id: target.id,
-                                            timer: Timer::from_seconds(0.6, TimerMode::Once),
-                                            entity,
-                                        });
-                                    }
+    let (offset_x, offset_y) = game.level.offset();
+    let prediction = AttackPrediction::new(&game);
 
-                                    let target_position = target_position.clone();
-
-                                    if unit.attack_pattern.charge {
-                                        let mut units = units_obstacles.p0();
-                                        let (_, mut position, mut transform, _) = units
-                                            .iter_mut()
-                                            .find(|(unit, _, _, _)| unit.id == *id)
-                                            .unwrap();
-
-                                        let Position(col, row) = *position;
-                                        let Position(target_col, target_row) = target_position;
-                                        if col < target_col {
-                                            position.0 = target_col - 1;
-                                        } else if col > target_col {
-                                            position.0 = target_col + 1;
-                                        }
-                                        if row < target_row {
-                                            position.1 = target_row - 1;
-                                        } else if row > target_row {
-                                            position.1 = target_row + 1;
-                                        }
-
-                                        let Position(col, row) = *position;
-                                        transform.translation.x = col as f32 * 64.0 - offset_x;
-                                        transform.translation.y = offset_y - row as f32 * 64.0;
-                                    }
+    for Position(col, row) in &prediction.tiles {
+        commands.spawn((
+            AttackOverlay,
+            SpriteSheetBundle {
+                sprite: Sprite {
+                    color: Color::rgba(1.0, 0.3, 0.3, 0.6),
+                    ..default()
+                },
+                texture: sprites.selections.0.clone(),
+                atlas: TextureAtlas {
+                    layout: sprites.selections.1.clone(),
+                    index: 2,
+                },
+                transform: Transform::from_xyz(
+                    *col as f32 * 64.0 - offset_x,
+                    offset_y - *row as f32 * 64.0,
+                    -1.5,
+                )
+                .with_scale(Vec3::splat(2.0)),
+                ..default()
+            },
+        ));
+    }
 
-                                    if !unit.attack_pattern.aoe {
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            turn.next();
-        }
+    for (id, damage, dies) in &prediction.hits {
+        let Some((_, Position(col, row))) = game.unit(*id) else {
+            continue;
+        };
+        let (value, color) = if *dies {
+            (format!("-{} KO", damage), Color::RED)
+        } else {
+            (format!("-{}", damage), Color::YELLOW)
+        };
+
+        commands.spawn((
+            AttackOverlay,
+            Text2dBundle {
+                text: Text::from_section(
+                    value,
+                    TextStyle {
+                        font_size: 20.0,
+                        color,
+                        ..TextStyle::default()
+                    },
+                ),
+                transform: Transform::from_xyz(
+                    *col as f32 * 64.0 - offset_x,
+                    offset_y - *row as f32 * 64.0 + 24.0,
+                    1.0,
+                ),
+                ..default()
+            },
+        ));
     }
 }
 
-fn win(
-    mut events: EventWriter<ChangeLevel>,
-    animation_queue: Res<AnimationQueue>,
-    level: Res<CurrentLevel>,
-    dna: Res<Dna>,
-    units: Query<&Unit>,
+/// Shades every tile the humans could attack on their next turn, redder the more damage
+/// could land there, whenever the board changes.
+fn show_threat_overlay(
+    mut commands: Commands,
+    overlays: Res<Overlays>,
+    game: Res<GameState>,
+    threat_overlays: Query<Entity, With<ThreatOverlay>>,
 ) {
-    if !animation_queue.queue.is_empty() {
+    if !overlays.is_changed() && !game.is_changed() {
         return;
     }
 
-    // Parasites win
-    if units.iter().all(|unit| unit.parasite) {
-        let CurrentLevel(level) = &*level;
-        let level_id = if level.id + 1 >= levels().len() {
-            0
-        } else {
-            level.id + 1
-        };
-        events.send(ChangeLevel { level_id });
+    for entity in threat_overlays.iter() {
+        commands
+            .entity(entity)
+            .remove::<(ThreatOverlay, SpriteBundle)>();
+    }
+
+    if !overlays.threats {
+        return;
     }
 
-    // Humans win
-    if units.iter().all(|unit| !unit.parasite) && units.iter().all(|unit| unit.dna * 2 > dna.0) {
-        let CurrentLevel(level) = &*level;
-        events.send(ChangeLevel { level_id: level.id });
+    let (offset_x, offset_y) = game.level.offset();
+    let threats = ThreatMap::new(&game);
+    let max = threats.max().max(1) as f32;
+
+    for (row, tiles) in threats.damage.iter().enumerate() {
+        for (col, damage) in tiles.iter().enumerate() {
+            if *damage == 0 {
+                continue;
+            }
+
+            commands.spawn((
+                ThreatOverlay,
+                SpriteBundle {
+                    sprite: Sprite {
+                        color: Color::rgba(1.0, 0.1, 0.0, 0.15 + 0.45 * *damage as f32 / max),
+                        custom_size: Some(Vec2::splat(64.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_xyz(
+                        col as f32 * 64.0 - offset_x,
+                        offset_y - row as f32 * 64.0,
+                        -1.75,
+                    ),
+                    ..default()
+                },
+            ));
+        }
     }
 }
 
 fn move_camera(
-    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
     mut mouse_motion: EventReader<MouseMotion>,
-    level: Res<CurrentLevel>,
-    mut camera: Query<&mut Transform, With<Camera>>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut window_resized: EventReader<WindowResized>,
+    time: Res<Time>,
+    bindings: Res<Bindings>,
+    actions: Res<Actions>,
+    cursor: Res<GridCursor>,
+    selected: Res<Selected>,
+    game: Res<GameState>,
+    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
 ) {
-    if mouse_button_input.pressed(MouseButton::Right) {
-        let mut camera_transform = camera.iter_mut().next().unwrap();
-        let CurrentLevel(level) = &*level;
-        let (width, height) = level.dimensions();
-        let (offset_x, offset_y) = level.offset();
+    let window = q_windows.single();
+    let (mut camera_transform, mut projection) = camera.single_mut();
+    let level = &game.level;
+    let (offset_x, offset_y) = level.offset();
+    let before = (camera_transform.translation, projection.scale);
+
+    let mut zoom: f32 = mouse_wheel
+        .read()
+        .map(|wheel| match wheel.unit {
+            MouseScrollUnit::Line => wheel.y,
+            MouseScrollUnit::Pixel => wheel.y / 100.0,
+        })
+        .sum();
+    if actions.just_pressed(InputAction::ZoomIn) {
+        zoom += 1.0;
+    }
+    if actions.just_pressed(InputAction::ZoomOut) {
+        zoom -= 1.0;
+    }
+    if zoom != 0.0 {
+        projection.scale = (projection.scale * ZOOM_STEP.powf(-zoom)).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+    let scale = projection.scale;
 
+    let mut pan = actions.pan;
+    if actions.pressed(InputAction::PanCamera) {
         for motion in mouse_motion.read() {
-            let min_camera_x = -(BORDER as f32) * 64.0 - offset_x + GAME_WIDTH / 2.0;
-            let max_camera_x =
-                (width as i16 + BORDER - 1) as f32 * 64.0 - offset_x - GAME_WIDTH / 2.0;
-            let min_camera_y = -(BORDER as f32) * 64.0 - offset_y + GAME_HEIGHT / 2.0;
-            let max_camera_y =
-                (height as i16 + BORDER - 1) as f32 * 64.0 - offset_y - GAME_HEIGHT / 2.0;
-
-            camera_transform.translation.x -= motion.delta.x;
-            if camera_transform.translation.x < min_camera_x {
-                camera_transform.translation.x = min_camera_x;
-            } else if camera_transform.translation.x > max_camera_x {
-                camera_transform.translation.x = max_camera_x;
-            }
+            pan += Vec2::new(-motion.delta.x, motion.delta.y);
+        }
+    }
 
-            camera_transform.translation.y += motion.delta.y;
-            if camera_transform.translation.y < min_camera_y {
-                camera_transform.translation.y = min_camera_y;
-            } else if camera_transform.translation.y > max_camera_y {
-                camera_transform.translation.y = max_camera_y;
-            }
+    if bindings.edge_scroll {
+        if let Some(position) = window.cursor_position() {
+            let edge = |position: f32, size: f32| {
+                if position < EDGE_SCROLL_MARGIN {
+                    -1.0
+                } else if position > size - EDGE_SCROLL_MARGIN {
+                    1.0
+                } else {
+                    0.0
+                }
+            };
+            let direction = Vec2::new(
+                edge(position.x, window.width()),
+                -edge(position.y, window.height()),
+            );
+            pan += direction * PAN_SPEED * time.delta_seconds();
         }
     }
+
+    camera_transform.translation += (pan * scale).extend(0.0);
+
+    let cell_to_world =
+        |Position(col, row): Position| (col as f32 * 64.0 - offset_x, offset_y - row as f32 * 64.0);
+
+    if actions.just_pressed(InputAction::CenterCamera) {
+        let target = selected
+            .0
+            .and_then(|id| game.unit(id))
+            .map(|(_, position)| *position)
+            .or(cursor.0);
+        let (x, y) = target.map_or((0.0, 0.0), cell_to_world);
+        camera_transform.translation.x = x;
+        camera_transform.translation.y = y;
+    } else if let (true, Some(cell)) = (actions.follow_cursor, cursor.0) {
+        // Scroll just far enough to keep a cursor moved by keys or buttons on screen
+        let (x, y) = cell_to_world(cell);
+        let half_width = (window.width() / 2.0 - 128.0).max(0.0) * scale;
+        let half_height = (window.height() / 2.0 - 96.0).max(0.0) * scale;
+        let translation = &mut camera_transform.translation;
+        translation.x = translation.x.clamp(x - half_width, x + half_width);
+        translation.y = translation.y.clamp(y - half_height, y + half_height);
+    }
+
+    let view = Vec2::new(window.width(), window.height()) * scale;
+    if (camera_transform.translation, projection.scale) != before
+        || window_resized.read().count() > 0
+    {
+        clamp_camera(&mut camera_transform.translation, level, view);
+    }
+}
+
+/// Keeps the camera from showing more than `BORDER` tiles past the level, given the size of
+/// the world area in view, and centres levels that fit on screen.
+fn clamp_camera(translation: &mut Vec3, level: &Level, view: Vec2) {
+    let (offset_x, offset_y) = level.offset();
+    let border = BORDER as f32 * 64.0;
+
+    // Tile centres run from -offset to offset on both axes
+    let clamp = |value: f32, offset: f32, half_view: f32| {
+        let limit = offset + border - half_view;
+        if limit > 0.0 {
+            value.clamp(-limit, limit)
+        } else {
+            0.0
+        }
+    };
+    translation.x = clamp(translation.x, offset_x, view.x / 2.0);
+    translation.y = clamp(translation.y, offset_y, view.y / 2.0);
+}
+
+/// Scales the side panel and buttons with the window so they fit small windows
+/// and stay readable in large ones.
+fn scale_ui(mut window_resized: EventReader<WindowResized>, mut ui_scale: ResMut<UiScale>) {
+    if let Some(resized) = window_resized.read().last() {
+        ui_scale.0 = (resized.width / GAME_WIDTH)
+            .min(resized.height / GAME_HEIGHT)
+            .clamp(0.5, 2.0);
+    }
 }
 
 fn animate(
@@ -1983,7 +3273,7 @@ fn animate(
     time: Res<Time>,
     sprites: Res<Sprites>,
     mut animation_queue: ResMut<AnimationQueue>,
-    level: Res<CurrentLevel>,
+    game: Res<GameState>,
     mut animations_units: ParamSet<(
         Query<(&mut AnimationTimer, &mut TextureAtlas)>,
         Query<(
@@ -1992,6 +3282,7 @@ fn animate(
             &mut AnimationTimer,
             &mut TextureAtlas,
         )>,
+        Query<(&mut Position, &mut Transform, Entity), With<Obstacle>>,
     )>,
 ) {
     for (mut timer, mut texture) in &mut animations_units.p0() {
@@ -2020,6 +3311,7 @@ fn animate(
                     *timer = new_timer;
                 }
             }
+            Animation::ObstacleMove { .. } | Animation::ObstacleDestroyed { .. } => {}
         }
 
         animation_queue.queue = animation_queue.queue[1..].to_vec();
@@ -2031,26 +3323,28 @@ fn animate(
     }
 
     if animation_queue.started {
-        let (id, offset) = match animation_queue.queue[0] {
-            Animation::UnitMove { id, .. } => (id, 1),
-            Animation::UnitAttack { id, .. } => (id, 2),
-            Animation::UnitDeath { id, .. } => (id, 3),
+        let unit_animation = match animation_queue.queue[0] {
+            Animation::UnitMove { id, .. } => Some((id, 1)),
+            Animation::UnitAttack { id, .. } => Some((id, 2)),
+            Animation::UnitDeath { id, .. } => Some((id, 3)),
+            Animation::ObstacleMove { .. } | Animation::ObstacleDestroyed { .. } => None,
         };
 
-        if let Some((unit, _, mut timer, mut texture)) =
-            units.iter_mut().find(|(unit, _, _, _)| unit.id == id)
-        {
-            let new_timer = sprites.units.2[unit.animation_index() + offset].clone();
-            texture.index = new_timer.first;
-            *timer = new_timer;
+        if let Some((id, offset)) = unit_animation {
+            if let Some((unit, _, mut timer, mut texture)) =
+                units.iter_mut().find(|(unit, _, _, _)| unit.id == id)
+            {
+                let new_timer = sprites.units.2[unit.animation_index() + offset].clone();
+                texture.index = new_timer.first;
+                *timer = new_timer;
+            }
         }
 
         animation_queue.started = false;
     }
 
     if !animation_queue.queue.is_empty() {
-        let CurrentLevel(level) = &*level;
-        let (offset_x, offset_y) = level.offset();
+        let (offset_x, offset_y) = game.level.offset();
         match &mut animation_queue.queue[0] {
             Animation::UnitMove {
                 id,
@@ -2097,289 +3391,54 @@ fn animate(
                     animation_queue.finished = true;
                 }
             }
-        }
-    }
-}
-
-fn possible_movements(
-    unit: &Unit,
-    position: &Position,
-    level: &Level,
-    units: &[(Unit, Position)],
-    obstacles: &[(Obstacle, Position)],
-) -> Vec<Position> {
-    let mut movements = Vec::new();
-    for direction in unit.move_direction.vectors() {
-        for dist in 1..=unit.speed {
-            let dcol = direction.0 * dist as isize;
-            let drow = direction.1 * dist as isize;
-            let Position(col, row) = position;
-
-            if -dcol > *col as isize || -drow > *row as isize {
-                break;
-            }
-
-            let movement = Position(
-                (*col as isize + dcol) as usize,
-                (*row as isize + drow) as usize,
-            );
-            let Position(col, row) = movement;
-
-            let width = level.tilemap[0].len();
-            let height = level.tilemap.len();
-
-            if col >= width
-                || row >= height
-                || level.tilemap[row][col] == 0
-                || units.iter().any(|(_, position)| *position == movement)
-                || obstacles.iter().any(|(_, position)| *position == movement)
-            {
-                break;
-            } else {
-                movements.push(movement);
-            }
-        }
-    }
-    movements
-}
+            Animation::ObstacleMove {
+                start,
+                goal,
+                progress,
+            } => {
+                // The obstacle keeps its starting position until it arrives
+                if let Some((mut position, mut transform, _)) = animations_units
+                    .p2()
+                    .iter_mut()
+                    .find(|(position, _, _)| **position == *start)
+                {
+                    let Position(start_col, start_row) = start;
+                    let Position(goal_col, goal_row) = goal;
 
-fn possible_attacks(
-    unit: &Unit,
-    position: &Position,
-    level: &Level,
-    units: &[(Unit, Position)],
-    obstacles: &[(Obstacle, Position)],
-) -> Vec<Vec<Position>> {
-    let mut attacks = Vec::new();
-    let width = level.tilemap[0].len();
-    let height = level.tilemap.len();
-
-    for direction in unit.attack_pattern.direction.vectors() {
-        let mut attack_direction = Vec::new();
-        for dist in 1..=unit.range {
-            let dcol = direction.0 * dist as isize;
-            let drow = direction.1 * dist as isize;
-            let Position(col, row) = position;
-
-            if -dcol > *col as isize || -drow > *row as isize {
-                break;
-            }
+                    let col =
+                        *start_col as f32 + (*goal_col as f32 - *start_col as f32) * *progress;
+                    let row =
+                        *start_row as f32 + (*goal_row as f32 - *start_row as f32) * *progress;
 
-            let attack = Position(
-                (*col as isize + dcol) as usize,
-                (*row as isize + drow) as usize,
-            );
-            let Position(col, row) = attack;
+                    transform.translation.x = col * 64.0 - offset_x;
+                    transform.translation.y = offset_y - row * 64.0;
 
-            if col >= width
-                || row >= height
-                || level.tilemap[row][col] == 0
-                || obstacles.iter().any(|(_, position)| *position == attack)
-            {
-                break;
-            } else if units.iter().any(|(_, position)| *position == attack) {
-                attack_direction.push(attack);
-                if !unit.attack_pattern.aoe {
-                    break;
+                    if *progress >= 1.0 {
+                        *position = *goal;
+                        animation_queue.finished = true;
+                    } else {
+                        *progress +=
+                            1.0 / (distance(start, goal) as f32) * time.delta().as_secs_f32() * 4.0;
+                    }
+                } else {
+                    animation_queue.finished = true;
                 }
             }
-        }
-        attacks.push(attack_direction);
-    }
-
-    attacks
-}
-
-fn attack_positions(
-    unit: &Unit,
-    direction: (isize, isize),
-    position: &Position,
-    level: &Level,
-    units: &[(Unit, Position)],
-    obstacles: &[(Obstacle, Position)],
-) -> Vec<Position> {
-    let mut positions = Vec::new();
-    let width = level.tilemap[0].len();
-    let height = level.tilemap.len();
-
-    for dist in 1..=unit.range {
-        let dcol = direction.0 * dist as isize;
-        let drow = direction.1 * dist as isize;
-        let Position(col, row) = position;
-
-        if -dcol > *col as isize || -drow > *row as isize {
-            break;
-        }
-
-        let attack_position = Position(
-            (*col as isize + dcol) as usize,
-            (*row as isize + drow) as usize,
-        );
-        let Position(col, row) = attack_position;
-
-        if col >= width
-            || row >= height
-            || level.tilemap[row][col] == 0
-            || obstacles
-                .iter()
-                .any(|(_, position)| *position == attack_position)
-        {
-            break;
-        } else if units
-            .iter()
-            .any(|(_, position)| *position == attack_position)
-        {
-            positions.push(attack_position);
-            if !unit.attack_pattern.aoe {
-                break;
-            }
-        } else {
-            positions.push(attack_position);
-        }
-    }
-
-    positions
-}
-
-fn longest_range_attacks(
-    unit: &Unit,
-    target_position: &Position,
-    level: &Level,
-    units: &[(Unit, Position)],
-    obstacles: &[(Obstacle, Position)],
-) -> Vec<Vec<Position>> {
-    let mut attacks = Vec::new();
-    let width = level.tilemap[0].len();
-    let height = level.tilemap.len();
-
-    for direction in unit.attack_pattern.direction.vectors() {
-        let mut direction_attacks = Vec::new();
-        for dist in 1..=unit.range {
-            let dcol = direction.0 * dist as isize;
-            let drow = direction.1 * dist as isize;
-            let Position(col, row) = target_position;
-
-            if -dcol > *col as isize || -drow > *row as isize {
-                break;
-            }
-
-            let attack = Position(
-                (*col as isize + dcol) as usize,
-                (*row as isize + drow) as usize,
-            );
-            let Position(col, row) = attack;
-
-            if col >= width
-                || row >= height
-                || level.tilemap[row][col] == 0
-                || units
-                    .iter()
-                    .any(|(other_unit, position)| other_unit.id != unit.id && *position == attack)
-                || obstacles.iter().any(|(_, position)| *position == attack)
-            {
-                break;
-            } else {
-                direction_attacks.push(attack);
-            }
-        }
-        attacks.push(direction_attacks.iter().rev().copied().collect());
-    }
-
-    attacks
-}
-
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct Frontier {
-    priority: u16,
-    position: Position,
-}
-
-impl Ord for Frontier {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.priority.cmp(&self.priority).then_with(|| {
-            [self.position.0, self.position.1].cmp(&[other.position.0, other.position.1])
-        })
-    }
-}
-
-impl PartialOrd for Frontier {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-// A* algorithm
-fn pathfind(
-    unit: &Unit,
-    start: &Position,
-    goal: &Position,
-    level: &Level,
-    units: &[(Unit, Position)],
-    obstacles: &[(Obstacle, Position)],
-) -> Option<(Vec<Position>, u16)> {
-    let mut frontier = BinaryHeap::new();
-    let mut came_from = HashMap::new();
-    let mut costs = HashMap::new();
-
-    frontier.push(Frontier {
-        priority: 0,
-        position: start.clone(),
-    });
-    costs.insert(start.clone(), 0);
-
-    while let Some(Frontier {
-        priority: _,
-        position,
-    }) = frontier.pop()
-    {
-        if position == *goal {
-            break;
-        }
-
-        let units: Vec<_> = units
-            .iter()
-            .map(|(other_unit, other_position)| {
-                if other_unit.id == unit.id {
-                    (other_unit.clone(), position.clone())
-                } else {
-                    (other_unit.clone(), other_position.clone())
+            Animation::ObstacleDestroyed { position, timer } => {
+                timer.tick(time.delta());
+                if timer.just_finished() {
+                    if let Some((_, _, entity)) = animations_units
+                        .p2()
+                        .iter()
+                        .find(|(obstacle_position, _, _)| **obstacle_position == *position)
+                    {
+                        commands
+                            .entity(entity)
+                            .remove::<(Obstacle, Position, SpriteSheetBundle)>();
+                    }
+                    animation_queue.finished = true;
                 }
-            })
-            .collect();
-
-        for movement in possible_movements(unit, &position, level, &units, obstacles) {
-            let new_cost = costs.get(&position).unwrap() + 1;
-            if !costs.contains_key(&movement) || new_cost < *costs.get(&movement).unwrap() {
-                frontier.push(Frontier {
-                    priority: new_cost + distance(goal, &movement),
-                    position: movement.clone(),
-                });
-                came_from.insert(movement.clone(), Some(position.clone()));
-                costs.insert(movement, new_cost);
             }
         }
     }
-
-    let mut position = goal;
-    let mut path = Vec::new();
-
-    while position != start {
-        path.push(position.clone());
-        position = match came_from.get(&position) {
-            Some(Some(position)) => position,
-            _ => return None,
-        };
-    }
-
-    path.reverse();
-
-    Some((path, *costs.get(&goal).unwrap()))
-}
-
-fn distance(a: &Position, b: &Position) -> u16 {
-    let Position(acol, arow) = a;
-    let Position(bcol, brow) = b;
-    let col_diff = (*acol as f32 - *bcol as f32).abs();
-    let row_diff = (*arow as f32 - *brow as f32).abs();
-    (col_diff * col_diff + row_diff * row_diff).sqrt() as u16
 }
@@ -0,0 +1,117 @@
+use crate::{
+    attack_positions, longest_range_attacks, possible_movements, GameState, Position, Turn,
+};
+
+/// What the humans' telegraphed attacks will do if the parasites stop where they are.
+#[derive(Debug, Clone, Default)]
+pub struct AttackPrediction {
+    /// Every tile a telegraphed attack reaches.
+    pub tiles: Vec<Position>,
+    /// Each unit that will be hurt, by id, with the damage it takes and whether it dies.
+    pub hits: Vec<(usize, u16, bool)>,
+}
+
+impl AttackPrediction {
+    /// Plays the attack phase out on a copy of the game. Before the humans have moved,
+    /// their telegraphs are from last round and nothing is predicted.
+    pub fn new(game: &GameState) -> Self {
+        if game.turn == Turn::HumansMove {
+            return Self::default();
+        }
+
+        let mut tiles = Vec::new();
+        for (unit, position) in &game.units {
+            if unit.parasite {
+                continue;
+            }
+            for direction in unit.attack_directions.iter().flatten() {
+                for tile in attack_positions(
+                    unit,
+                    *direction,
+                    position,
+                    &game.level,
+                    &game.units,
+                    &game.obstacles,
+                ) {
+                    if !tiles.contains(&tile) {
+                        tiles.push(tile);
+                    }
+                }
+            }
+        }
+
+        let mut after = game.clone();
+        after.history.clear();
+        after.turn = Turn::HumansAttack;
+        after.advance();
+
+        let hits = game
+            .units
+            .iter()
+            .filter_map(|(unit, _)| {
+                let health = after
+                    .unit(unit.id)
+                    .map_or(0, |(survivor, _)| survivor.health);
+                let damage = unit.health - health;
+                (damage > 0).then_some((unit.id, damage, health == 0))
+            })
+            .collect();
+
+        Self { tiles, hits }
+    }
+}
+
+/// How much damage the humans could deal to each tile on their next attack,
+/// if they moved anywhere they can reach to line it up.
+#[derive(Debug, Clone, Default)]
+pub struct ThreatMap {
+    /// Potential damage by row, then column.
+    pub damage: Vec<Vec<u16>>,
+}
+
+impl ThreatMap {
+    pub fn new(game: &GameState) -> Self {
+        let level = &game.level;
+        let (width, height) = level.dimensions();
+        let mut damage = vec![vec![0; width]; height];
+
+        for (unit, position) in &game.units {
+            if unit.parasite {
+                continue;
+            }
+
+            let mut reach = vec![*position];
+            reach.extend(possible_movements(
+                unit,
+                position,
+                level,
+                &game.units,
+                &game.obstacles,
+            ));
+
+            for (row, tiles) in damage.iter_mut().enumerate() {
+                for (col, tile) in tiles.iter_mut().enumerate() {
+                    let target = Position(col, row);
+                    if !level.terrain(&target).walkable() {
+                        continue;
+                    }
+
+                    let threatened =
+                        longest_range_attacks(unit, &target, level, &game.units, &game.obstacles)
+                            .iter()
+                            .flatten()
+                            .any(|attack_position| reach.contains(attack_position));
+                    if threatened {
+                        *tile += unit.damage;
+                    }
+                }
+            }
+        }
+
+        Self { damage }
+    }
+
+    pub fn max(&self) -> u16 {
+        self.damage.iter().flatten().copied().max().unwrap_or(0)
+    }
+}
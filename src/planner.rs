@@ -0,0 +1,223 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+use crate::{attack_positions, distance, possible_movements, GameState, Position, Turn, Unit};
+
+/// How many partial plans are kept after each human is planned.
+const BEAM_WIDTH: usize = 6;
+
+/// Score for a parasite killed, per point of its DNA value.
+const KILL_WEIGHT: i32 = 40;
+const DAMAGE_WEIGHT: i32 = 10;
+/// Hurting a human costs more than hurting a parasite earns.
+const FRIENDLY_FIRE_WEIGHT: i32 = 15;
+const HAZARD_WEIGHT: i32 = 5;
+/// Score for covering every cell a parasite could flee to, scaled down for partial cover.
+const PRESSURE_WEIGHT: i32 = 30;
+
+/// How the humans pick their moves and telegraphed attacks. Chosen per level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HumanAi {
+    /// Each human paths to the nearest cell it can attack from, on its own.
+    #[default]
+    Greedy,
+    /// The whole squad is planned together with a beam search over cloned boards.
+    BeamSearch,
+}
+
+impl HumanAi {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Greedy => "Greedy",
+            Self::BeamSearch => "Beam Search",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            Self::Greedy => Self::BeamSearch,
+            Self::BeamSearch => Self::Greedy,
+        }
+    }
+}
+
+/// A partial plan: the board with every human planned so far moved and aimed.
+#[derive(Clone)]
+struct Beam {
+    units: Vec<(Unit, Position)>,
+    score: i32,
+}
+
+/// Plans the human move phase for the whole squad at once, returning the units with
+/// humans moved and their attacks telegraphed.
+///
+/// Humans are planned one at a time in turn order. Every move and aim of the next human
+/// is tried on each kept plan, and only the best few plans are carried on to the next.
+/// Every candidate is scored on the same scratch board, so the game is only cloned once.
+pub fn plan_humans(game: &GameState) -> Vec<(Unit, Position)> {
+    let mut scratch = game.clone();
+    scratch.actions.clear();
+    scratch.history.clear();
+
+    let mut units = game.units.clone();
+    for (unit, _) in &mut units {
+        if !unit.parasite {
+            unit.attack_directions = None;
+        }
+    }
+    let mut beams = vec![Beam {
+        score: evaluate(game, &mut scratch, &units),
+        units,
+    }];
+
+    for id in &game.turn_order {
+        let mut candidates = Vec::new();
+
+        for beam in &beams {
+            let Some(index) = beam
+                .units
+                .iter()
+                .position(|(unit, _)| unit.id == *id && !unit.parasite)
+            else {
+                candidates.push(beam.clone());
+                continue;
+            };
+
+            let (unit, position) = &beam.units[index];
+            let mut goals = vec![*position];
+            goals.extend(possible_movements(
+                unit,
+                position,
+                &game.level,
+                &beam.units,
+                &game.obstacles,
+            ));
+
+            for goal in goals {
+                for attack_directions in aims(unit) {
+                    let mut units = beam.units.clone();
+                    units[index].1 = goal;
+                    units[index].0.attack_directions = attack_directions;
+                    candidates.push(Beam {
+                        score: evaluate(game, &mut scratch, &units),
+                        units,
+                    });
+                }
+            }
+        }
+
+        candidates.sort_by_key(|beam| Reverse(beam.score));
+        candidates.truncate(BEAM_WIDTH);
+        beams = candidates;
+    }
+
+    beams.swap_remove(0).units
+}
+
+/// Every way a human may telegraph its attack, including holding fire.
+fn aims(unit: &Unit) -> Vec<Option<Vec<(isize, isize)>>> {
+    let vectors = unit.attack_pattern.direction.vectors();
+    if unit.attack_pattern.all_directions {
+        return vec![Some(vectors), None];
+    }
+
+    vectors
+        .into_iter()
+        .map(|direction| Some(vec![direction]))
+        .chain([None])
+        .collect()
+}
+
+/// Scores a board with some humans moved and aimed.
+///
+/// The attack phase is played out twice: once as if the parasites stood still, and once
+/// as if they all got out of the way, to catch shots that would carry on into other
+/// humans. Aiming at the cells parasites could flee to also counts, as do getting
+/// closer to the nearest parasite and staying off hazardous terrain.
+fn evaluate(game: &GameState, scratch: &mut GameState, units: &[(Unit, Position)]) -> i32 {
+    let humans: Vec<_> = units
+        .iter()
+        .filter(|(unit, _)| !unit.parasite)
+        .cloned()
+        .collect();
+
+    let mut score = play_attacks(game, scratch, units) + play_attacks(game, scratch, &humans) / 2;
+    for (unit, position) in units {
+        if unit.parasite {
+            score += pressure(game, units, &humans, unit, position);
+        } else {
+            score -= game.level.terrain(position).damage_per_turn() as i32 * HAZARD_WEIGHT;
+            score -= units
+                .iter()
+                .filter(|(target, _)| target.parasite)
+                .map(|(_, target_position)| distance(position, target_position) as i32)
+                .min()
+                .unwrap_or(0);
+        }
+    }
+
+    score
+}
+
+/// Plays out the attack phase on the scratch board, reset to `game`'s obstacles and the
+/// given units, and scores what it did to both sides.
+fn play_attacks(game: &GameState, scratch: &mut GameState, units: &[(Unit, Position)]) -> i32 {
+    scratch.units.clear();
+    scratch.units.extend_from_slice(units);
+    scratch.obstacles.clone_from(&game.obstacles);
+    scratch.turn = Turn::HumansAttack;
+    scratch.advance();
+
+    let mut score = 0;
+    for (unit, _) in units {
+        let survivor = scratch.unit(unit.id).map(|(survivor, _)| survivor);
+        let damage = unit.health - survivor.map_or(0, |survivor| survivor.health);
+        let value = if survivor.is_none() {
+            damage as i32 * DAMAGE_WEIGHT + unit.dna as i32 * KILL_WEIGHT
+        } else {
+            damage as i32 * DAMAGE_WEIGHT
+        };
+
+        if unit.parasite {
+            score += value;
+        } else {
+            score -= value * FRIENDLY_FIRE_WEIGHT / DAMAGE_WEIGHT;
+        }
+    }
+
+    score
+}
+
+/// How much of the ground a parasite could end its turn on lies in a telegraphed attack.
+fn pressure(
+    game: &GameState,
+    units: &[(Unit, Position)],
+    humans: &[(Unit, Position)],
+    parasite: &Unit,
+    position: &Position,
+) -> i32 {
+    let level = &game.level;
+    let mut escapes = vec![*position];
+    escapes.extend(possible_movements(
+        parasite,
+        position,
+        level,
+        units,
+        &game.obstacles,
+    ));
+
+    let covered = escapes
+        .iter()
+        .filter(|escape| {
+            humans.iter().any(|(human, human_position)| {
+                human.attack_directions.iter().flatten().any(|direction| {
+                    let obstacles = &game.obstacles;
+                    attack_positions(human, *direction, human_position, level, humans, obstacles)
+                        .contains(escape)
+                })
+            })
+        })
+        .count();
+
+    covered as i32 * PRESSURE_WEIGHT / escapes.len() as i32
+}
@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{DifficultySettings, GameAction, GameState};
+
+/// Every parasite action taken during one attempt at a level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub level_id: usize,
+    pub actions: Vec<GameAction>,
+    #[serde(default)]
+    pub difficulty: DifficultySettings,
+    /// The seed the level was generated from, if it was a challenge.
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+impl Replay {
+    pub fn new(game: &GameState, seed: Option<u64>) -> Self {
+        Self {
+            level_id: game.level.id,
+            actions: game.actions.clone(),
+            difficulty: game.difficulty,
+            seed,
+        }
+    }
+
+    /// Challenges are kept by seed, since their level ids change between sessions.
+    pub fn path(level_id: usize, seed: Option<u64>) -> Option<PathBuf> {
+        let name = match seed {
+            Some(seed) => format!("challenge-{}.ron", seed),
+            None => format!("level-{}.ron", level_id),
+        };
+        Some(
+            dirs::data_dir()?
+                .join("pestilence")
+                .join("replays")
+                .join(name),
+        )
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        ron::from_str(&contents).map_err(|err| err.to_string())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+}
+
+/// Actions still to be played back from a loaded replay.
+#[derive(Debug, Clone, Resource)]
+pub struct Playback {
+    pub level_id: usize,
+    pub actions: VecDeque<GameAction>,
+}
+
+/// Left behind when a replay finishes or diverges, so a game the player takes over
+/// isn't saved over the replay it came from. Cleared when the next game starts.
+#[derive(Debug, Clone, Resource)]
+pub struct PlaybackEnded;
+
+impl From<Replay> for Playback {
+    fn from(replay: Replay) -> Self {
+        Self {
+            level_id: replay.level_id,
+            actions: replay.actions.into(),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::GameState;
+
+/// Bumped whenever the saved `GameState` layout changes.
+pub const SAVE_VERSION: u32 = 3;
+
+/// Slot 0 is written automatically at the start of every round.
+pub const AUTOSAVE_SLOT: usize = 0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub game: GameState,
+}
+
+impl SaveFile {
+    pub fn path(slot: usize) -> Option<PathBuf> {
+        let name = if slot == AUTOSAVE_SLOT {
+            "autosave.ron".into()
+        } else {
+            format!("slot-{}.ron", slot)
+        };
+        Some(
+            dirs::data_dir()?
+                .join("pestilence")
+                .join("saves")
+                .join(name),
+        )
+    }
+
+    pub fn load(slot: usize) -> Result<GameState, String> {
+        let path = Self::path(slot).ok_or("No user data directory")?;
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let save: Self = ron::from_str(&contents).map_err(|err| err.to_string())?;
+
+        if save.version != SAVE_VERSION {
+            return Err(format!(
+                "Save version {} is not supported (expected {})",
+                save.version, SAVE_VERSION
+            ));
+        }
+
+        Ok(save.game)
+    }
+
+    pub fn save(slot: usize, game: &GameState) -> Result<(), String> {
+        let path = Self::path(slot).ok_or("No user data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+
+        let save = Self {
+            version: SAVE_VERSION,
+            game: game.clone(),
+        };
+        let contents = ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::default())
+            .map_err(|err| err.to_string())?;
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+}
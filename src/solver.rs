@@ -0,0 +1,251 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::ops::RangeInclusive;
+
+use crate::{GameAction, GameState, Level, Obstacle, Position, Turn, UnitRegistry, Winner};
+
+/// Boards kept after each round of the search.
+const BEAM_WIDTH: usize = 8;
+/// Parasite turns sampled from every kept board each round.
+const TURNS_PER_BOARD: usize = 12;
+/// Rounds searched before a DNA amount is given up on.
+pub const MAX_ROUNDS: usize = 40;
+/// Chance in 100 that a sampled turn ends before every action has been used.
+const EARLY_END_CHANCE: u32 = 10;
+
+/// A winning line found by the solver.
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub dna: u16,
+    pub actions: Vec<GameAction>,
+}
+
+impl Solution {
+    /// The winning actions split into parasite turns, each ending with `EndTurn`
+    /// except possibly the last.
+    pub fn turns(&self) -> Vec<&[GameAction]> {
+        self.actions
+            .split_inclusive(|action| *action == GameAction::EndTurn)
+            .collect()
+    }
+}
+
+/// What the solver found for one level.
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// The cheapest win found, at any amount of DNA.
+    pub minimal: Option<Solution>,
+    /// Whether a win was found with the level's own `initial_dna`.
+    pub winnable: bool,
+}
+
+/// Searches for the least starting DNA the parasites can win the level with, against the
+/// human AI at the default difficulty.
+///
+/// The search samples whole parasite turns rather than trying every action, so a level
+/// without a win found has none the solver could find, not necessarily none at all, and the
+/// least DNA reported is an upper bound. Each DNA amount is searched with its own rng seeded
+/// from `seed`, so it finds the same thing whichever order the amounts are tried in.
+pub fn solve(level: &Level, registry: &UnitRegistry, seed: u64) -> Report {
+    let (cheapest, everyone) = dna_range(level, registry).into_inner();
+
+    let probe = |dna: u16| {
+        let mut rng = StdRng::seed_from_u64(seed ^ ((dna as u64) << 32));
+        let level = Level {
+            initial_dna: dna,
+            ..level.clone()
+        };
+        search(&mut rng, &level, registry)
+    };
+    let mut minimal = least_dna(cheapest..=everyone.max(level.initial_dna), probe)
+        .map(|(dna, actions)| Solution { dna, actions });
+
+    let mut winnable = minimal
+        .as_ref()
+        .is_some_and(|solution| solution.dna <= level.initial_dna);
+
+    // The search may miss a win at one amount and find one at less, so a level that
+    // seems to need more than it has is searched again at its own DNA
+    if !winnable {
+        if let Some(actions) = probe(level.initial_dna) {
+            minimal = Some(Solution {
+                dna: level.initial_dna,
+                actions,
+            });
+            winnable = true;
+        }
+    }
+
+    Report { minimal, winnable }
+}
+
+/// The starting DNA worth searching a level over: from enough to infect the cheapest human
+/// to enough to infect the whole squad at once, which always wins.
+pub fn dna_range(level: &Level, registry: &UnitRegistry) -> RangeInclusive<u16> {
+    let costs: Vec<u16> = GameState::new(level.clone(), registry)
+        .units
+        .iter()
+        .map(|(unit, _)| unit.dna * 2)
+        .collect();
+    let cheapest = costs.iter().copied().min().unwrap_or(0);
+    cheapest..=costs.iter().sum()
+}
+
+/// Finds the least DNA in `range` that `wins` finds a win with, returning it with what
+/// `wins` found. More DNA is taken never to make a level harder, so the search steps up
+/// from the bottom in growing strides until it wins, then binary searches the last stride.
+/// When `wins` only samples, as the solver does, that doesn't strictly hold, and the amount
+/// found is a heuristic: a win is known there, but it may be missed at some amounts below.
+pub fn least_dna<T>(
+    range: RangeInclusive<u16>,
+    mut wins: impl FnMut(u16) -> Option<T>,
+) -> Option<(u16, T)> {
+    let (mut low, end) = range.into_inner();
+    let mut stride = 1;
+    let mut least = loop {
+        if low > end {
+            return None;
+        }
+        let dna = low.saturating_add(stride - 1).min(end);
+        if let Some(found) = wins(dna) {
+            break (dna, found);
+        }
+        low = dna.checked_add(1)?;
+        stride = stride.saturating_mul(2);
+    };
+
+    let mut high = least.0;
+    while low < high {
+        let dna = low + (high - low) / 2;
+        match wins(dna) {
+            Some(found) => {
+                least = (dna, found);
+                high = dna;
+            }
+            None => low = dna + 1,
+        }
+    }
+
+    Some(least)
+}
+
+/// Beam search over parasite turns, returning the actions of the first win found.
+fn search(rng: &mut StdRng, level: &Level, registry: &UnitRegistry) -> Option<Vec<GameAction>> {
+    let mut game = GameState::new(level.clone(), registry);
+    finish_humans(&mut game);
+    let mut beams = vec![game];
+
+    for _ in 0..MAX_ROUNDS {
+        let mut candidates = Vec::new();
+
+        for game in &beams {
+            for _ in 0..TURNS_PER_BOARD {
+                let mut game = game.clone();
+                sample_turn(rng, &mut game);
+                finish_humans(&mut game);
+
+                match game.winner() {
+                    Some(Winner::Parasites) => return Some(game.actions),
+                    Some(Winner::Humans) => {}
+                    None => candidates.push((score(&game), game)),
+                }
+            }
+        }
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        candidates.sort_by_key(|(score, _)| -score);
+        candidates.dedup_by(|(_, a), (_, b)| board(a) == board(b));
+        beams = candidates
+            .into_iter()
+            .take(BEAM_WIDTH)
+            .map(|(_, game)| game)
+            .collect();
+    }
+
+    None
+}
+
+/// Plays one parasite turn, ending once nothing is left to do, or now and then sooner.
+fn sample_turn(rng: &mut StdRng, game: &mut GameState) {
+    while game.turn == Turn::Parasites && game.winner().is_none() {
+        let action = sample_action(rng, game, 0.75, EARLY_END_CHANCE);
+        game.apply(action);
+        game.history.clear();
+    }
+}
+
+/// Picks a parasite action, favouring infecting, then attacking, then moving. The most
+/// favoured kind on offer is taken with `eagerness` chance, else the next one, and with
+/// `early_end` chance in 100 the turn is ended instead.
+pub fn sample_action(
+    rng: &mut StdRng,
+    game: &GameState,
+    eagerness: f64,
+    early_end: u32,
+) -> GameAction {
+    if early_end > 0 && rng.gen_range(0..100) < early_end {
+        return GameAction::EndTurn;
+    }
+
+    let actions = game.legal_actions();
+    let preferred = |matches: fn(&GameAction) -> bool| -> Vec<GameAction> {
+        actions
+            .iter()
+            .filter(|action| matches(action))
+            .cloned()
+            .collect()
+    };
+    let choices = [
+        preferred(|action| matches!(action, GameAction::Infect { .. })),
+        preferred(|action| matches!(action, GameAction::Attack { .. })),
+        preferred(|action| matches!(action, GameAction::Move { .. })),
+    ];
+
+    choices
+        .iter()
+        .filter(|choices| !choices.is_empty())
+        .find(|_| rng.gen_bool(eagerness))
+        .or_else(|| choices.iter().find(|choices| !choices.is_empty()))
+        .and_then(|choices| choices.choose(rng))
+        .cloned()
+        .unwrap_or(GameAction::EndTurn)
+}
+
+/// Lets the humans move and attack until it is the parasites' turn again or someone won.
+fn finish_humans(game: &mut GameState) {
+    while game.turn != Turn::Parasites && game.winner().is_none() {
+        game.advance();
+    }
+}
+
+/// What two boards reached by different lines must share to count as the same.
+fn board(
+    game: &GameState,
+) -> (
+    Vec<(usize, bool, u16, Position)>,
+    &[(Obstacle, Position)],
+    u16,
+) {
+    let units = game
+        .units
+        .iter()
+        .map(|(unit, position)| (unit.id, unit.parasite, unit.health, *position))
+        .collect();
+    (units, &game.obstacles, game.dna)
+}
+
+/// How promising a board looks for the parasites: what they hold, in DNA,
+/// against how much human health is left to get through.
+fn score(game: &GameState) -> i32 {
+    let mut score = game.dna as i32 * 10;
+    for (unit, _) in &game.units {
+        if unit.parasite {
+            score += unit.dna as i32 * 20 + unit.health as i32;
+        } else {
+            score -= unit.health as i32 * 2;
+        }
+    }
+    score
+}
@@ -0,0 +1,134 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::{AttackPattern, Direction, Unit, UnitType};
+
+pub const UNIT_REGISTRY: &str = "units/archetypes.units.ron";
+
+/// Number of unit sprite rows in `sprites/units.png`. Each row holds a human and a
+/// parasite variant, each with four animations.
+pub const UNIT_SPRITE_ROWS: usize = 7;
+
+/// The stats every unit of one type starts with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnitArchetype {
+    pub name: String,
+    pub max_health: u16,
+    pub damage: u16,
+    pub speed: u16,
+    pub range: u16,
+    pub move_direction: Direction,
+    pub attack_pattern: AttackPattern,
+    /// Awarded for a kill. Infecting costs twice as much.
+    pub dna: u16,
+    /// Lower orders act first during the human phases.
+    pub order: u16,
+    pub sprite_row: usize,
+}
+
+/// Every unit archetype, looked up by `UnitType`.
+#[derive(Debug, Clone, Asset, TypePath, Resource)]
+pub struct UnitRegistry(pub Vec<UnitArchetype>);
+
+impl UnitRegistry {
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let registry = Self(ron::de::from_bytes(bytes).map_err(|err| err.to_string())?);
+        registry.validate()?;
+        Ok(registry)
+    }
+
+    /// Reads the registry straight from disk, without the asset server.
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        fs::read(path)
+            .map_err(|err| err.to_string())
+            .and_then(|bytes| Self::parse(&bytes))
+            .map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for archetype in &self.0 {
+            if !names.insert(&archetype.name) {
+                return Err(format!("more than one unit named {}", archetype.name));
+            }
+            if archetype.sprite_row >= UNIT_SPRITE_ROWS {
+                return Err(format!(
+                    "{} uses sprite row {} but there are only {}",
+                    archetype.name, archetype.sprite_row, UNIT_SPRITE_ROWS
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, unit_type: &UnitType) -> Option<&UnitArchetype> {
+        self.0
+            .iter()
+            .find(|archetype| archetype.name == unit_type.name())
+    }
+
+    pub fn unit_types(&self) -> impl Iterator<Item = UnitType> + '_ {
+        self.0
+            .iter()
+            .map(|archetype| UnitType(archetype.name.clone()))
+    }
+
+    /// A fresh human of the given type, or `None` if there is no such type.
+    pub fn unit(&self, id: usize, unit_type: &UnitType) -> Option<Unit> {
+        let archetype = self.get(unit_type)?;
+        Some(Unit {
+            id,
+            kind: unit_type.clone(),
+            parasite: false,
+            max_health: archetype.max_health,
+            health: archetype.max_health,
+            damage: archetype.damage,
+            speed: archetype.speed,
+            range: archetype.range,
+            move_direction: archetype.move_direction.clone(),
+            attack_pattern: archetype.attack_pattern.clone(),
+            dna: archetype.dna,
+            sprite_row: archetype.sprite_row,
+            has_moved: false,
+            has_attacked: false,
+            attack_directions: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Resource)]
+pub struct UnitRegistryHandle(pub Handle<UnitRegistry>);
+
+#[derive(Default)]
+pub struct UnitRegistryLoader;
+
+impl AssetLoader for UnitRegistryLoader {
+    type Asset = UnitRegistry;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<UnitRegistry, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            Ok(UnitRegistry::parse(&bytes)?)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["units.ron"]
+    }
+}